@@ -0,0 +1,354 @@
+// std.
+use std::collections::HashMap;
+
+// logging.
+use log::{debug, warn};
+
+// Own.
+use crate::middleware::{BoxFuture, Middleware, Next, Outcome, Request};
+use crate::types::Message;
+use crate::WebexClient;
+
+// ###################################################################
+// Access policy for a single command.
+// ###################################################################
+
+/**
+ * Every non empty constraint has to be satisfied for an invocation to be
+ * allowed. The person constraints (email domains, person ids and emails)
+ * are alternatives: matching any one of them is enough.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    pub email_domains: Vec<String>, // Allowed email domains, e.g. "cisco.com".
+    pub person_ids: Vec<String>,    // Explicitly allowed person ids.
+    pub person_emails: Vec<String>, // Explicitly allowed person emails.
+    pub room_ids: Vec<String>,      // Rooms the command can be invoked from.
+    pub org_ids: Vec<String>,       // Organizations the invoker must belong to.
+    pub moderators_only: bool,      // Only room moderators can invoke the command.
+}
+
+impl AccessPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_email_domain(mut self, domain: &str) -> Self {
+        self.email_domains
+            .push(domain.trim_start_matches('@').to_lowercase());
+        self
+    }
+
+    pub fn allow_person_id(mut self, person_id: &str) -> Self {
+        self.person_ids.push(person_id.to_string());
+        self
+    }
+
+    pub fn allow_person_email(mut self, email: &str) -> Self {
+        self.person_emails.push(email.to_lowercase());
+        self
+    }
+
+    pub fn allow_room(mut self, room_id: &str) -> Self {
+        self.room_ids.push(room_id.to_string());
+        self
+    }
+
+    pub fn allow_org(mut self, org_id: &str) -> Self {
+        self.org_ids.push(org_id.to_string());
+        self
+    }
+
+    pub fn moderators_only(mut self) -> Self {
+        self.moderators_only = true;
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Check whether the author of the message satisfies this policy.
+    // ------------------------------------------------------------------------------
+
+    pub async fn is_allowed(&self, client: &WebexClient, message: &Message) -> bool {
+        let person_id = message.person_id.clone().unwrap_or_default();
+        let room_id = message.room_id.clone().unwrap_or_default();
+        let email = message
+            .person_email
+            .clone()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let has_person_rules = !self.email_domains.is_empty()
+            || !self.person_ids.is_empty()
+            || !self.person_emails.is_empty();
+
+        if has_person_rules {
+            let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+            let allowed = self.person_ids.contains(&person_id)
+                || self.person_emails.contains(&email)
+                || domain.is_some_and(|d| self.email_domains.iter().any(|a| a == d));
+            if !allowed {
+                debug!("{} is not within the allowed people.", email);
+                return false;
+            }
+        }
+
+        if !self.room_ids.is_empty() && !self.room_ids.contains(&room_id) {
+            debug!("Room {} is not within the allowed rooms.", room_id);
+            return false;
+        }
+
+        if !self.org_ids.is_empty() {
+            // A failed lookup denies the call rather than letting it through.
+            let person = match client.get_person_details(&person_id).await {
+                Ok(person) => person,
+                Err(e) => {
+                    warn!("Could not retrieve the person {}: {}", person_id, e);
+                    return false;
+                }
+            };
+            if !self.org_ids.contains(&person.org_id) {
+                debug!("Organization {} is not allowed.", person.org_id);
+                return false;
+            }
+        }
+
+        if self.moderators_only {
+            let memberships = match client.list_memberships(&room_id, Some(&person_id)).await {
+                Ok(memberships) => memberships,
+                Err(e) => {
+                    warn!("Could not list the memberships of room {}: {}", room_id, e);
+                    return false;
+                }
+            };
+            if !memberships.iter().any(|membership| membership.is_moderator) {
+                debug!("{} is not a moderator of room {}.", email, room_id);
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// ###################################################################
+// Middleware enforcing the access policies of every command.
+// ###################################################################
+
+pub struct Authorization {
+    policies: HashMap<String, AccessPolicy>,
+    fallback: Option<AccessPolicy>,
+    denied_reply: String,
+}
+
+impl Authorization {
+    const DENIED: &'static str = "Sorry, you are not allowed to use this command.";
+
+    pub fn new() -> Self {
+        Authorization {
+            policies: HashMap::new(),
+            fallback: None,
+            denied_reply: Self::DENIED.to_string(),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Restrict a command (or pattern) to the people matching the policy.
+    // ------------------------------------------------------------------------------
+
+    pub fn protect(mut self, command: &str, policy: AccessPolicy) -> Self {
+        self.policies.insert(command.to_string(), policy);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Policy applied to every command without a policy of its own.
+    // ------------------------------------------------------------------------------
+
+    pub fn fallback(mut self, policy: AccessPolicy) -> Self {
+        self.fallback = Some(policy);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Message replied to denied invocations.
+    // ------------------------------------------------------------------------------
+
+    pub fn denied_reply(mut self, reply: &str) -> Self {
+        self.denied_reply = reply.to_string();
+        self
+    }
+}
+
+impl Default for Authorization {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Authorization {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Outcome> {
+        Box::pin(async move {
+            let policy = match self
+                .policies
+                .get(&request.command)
+                .or(self.fallback.as_ref())
+            {
+                Some(policy) => policy,
+                None => return next.run(request).await,
+            };
+
+            if policy.is_allowed(&request.client, &request.message).await {
+                return next.run(request).await;
+            }
+
+            warn!(
+                "[Access denied]: {} tried to run {} in room {}",
+                request.message.person_email.as_deref().unwrap_or_default(),
+                request.command,
+                request.message.room_id.as_deref().unwrap_or_default()
+            );
+            Outcome::reply(&request.message, &self.denied_reply)
+        })
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Handler;
+    use crate::types::{ArgTuple, HandlerResult};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+
+    fn message(person_id: &str, email: &str, room_id: &str) -> Message {
+        Message {
+            person_id: Some(person_id.to_string()),
+            person_email: Some(email.to_string()),
+            room_id: Some(room_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn ok(
+        _: WebexClient,
+        _: Message,
+        _: ArgTuple,
+        _: ArgTuple,
+    ) -> Pin<Box<dyn Future<Output = HandlerResult> + Send + Sync>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // Whether the authorization lets the command through.
+    async fn runs(authorization: &Authorization, command: &str, message: Message) -> bool {
+        let middlewares: Vec<Arc<dyn Middleware>> = Vec::new();
+        let request = Request {
+            client: WebexClient::new("token"),
+            message,
+            command: command.to_string(),
+            required_arguments: Vec::new(),
+            optional_arguments: Vec::new(),
+        };
+        let next = Next::new(&middlewares, Handler::Command(ok));
+        match authorization.handle(request, next).await {
+            Outcome::Handled => true,
+            Outcome::Reply(reply) => {
+                assert_eq!(reply.text.as_deref(), Some(Authorization::DENIED));
+                false
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn person_rules_are_alternatives() {
+        let client = WebexClient::new("token");
+        let policy = AccessPolicy::new()
+            .allow_email_domain("@Cisco.com")
+            .allow_person_id("alice-id")
+            .allow_person_email("Bob@Example.com");
+
+        let cases = [
+            (message("someone", "someone@cisco.com", "room"), true),
+            (message("someone", "SOMEONE@CISCO.COM", "room"), true),
+            (message("alice-id", "alice@example.com", "room"), true),
+            (message("bob-id", "bob@example.com", "room"), true),
+            (message("eve-id", "eve@example.com", "room"), false),
+            (message("eve-id", "eve@notcisco.com", "room"), false),
+            (message("eve-id", "cisco.com", "room"), false),
+            (Message::default(), false),
+        ];
+        for (message, allowed) in cases {
+            assert_eq!(
+                policy.is_allowed(&client, &message).await,
+                allowed,
+                "{:?}",
+                message.person_email
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn room_rules_apply_on_top_of_the_person_rules() {
+        let client = WebexClient::new("token");
+        let rooms = AccessPolicy::new().allow_room("ops");
+        assert!(
+            rooms
+                .is_allowed(&client, &message("eve", "eve@x.com", "ops"))
+                .await
+        );
+        assert!(
+            !rooms
+                .is_allowed(&client, &message("eve", "eve@x.com", "dev"))
+                .await
+        );
+
+        let both = rooms.allow_email_domain("cisco.com");
+        assert!(
+            both.is_allowed(&client, &message("a", "a@cisco.com", "ops"))
+                .await
+        );
+        assert!(
+            !both
+                .is_allowed(&client, &message("a", "a@cisco.com", "dev"))
+                .await
+        );
+        assert!(
+            !both
+                .is_allowed(&client, &message("eve", "eve@x.com", "ops"))
+                .await
+        );
+
+        // An empty policy allows everyone.
+        let open = AccessPolicy::new();
+        assert!(open.is_allowed(&client, &Message::default()).await);
+    }
+
+    #[rocket::async_test]
+    async fn commands_use_their_policy_or_the_fallback() {
+        let insider = || message("a", "a@cisco.com", "room");
+        let outsider = || message("eve", "eve@x.com", "room");
+
+        let authorization = Authorization::new().protect(
+            "/deploy",
+            AccessPolicy::new().allow_email_domain("cisco.com"),
+        );
+        assert!(runs(&authorization, "/deploy", insider()).await);
+        assert!(!runs(&authorization, "/deploy", outsider()).await);
+        // Without a fallback, unprotected commands are open.
+        assert!(runs(&authorization, "/status", outsider()).await);
+
+        let authorization = authorization
+            .fallback(AccessPolicy::new().allow_person_id("eve"))
+            .protect("/help", AccessPolicy::new());
+        assert!(runs(&authorization, "/status", outsider()).await);
+        assert!(!runs(&authorization, "/status", insider()).await);
+        // A command policy replaces the fallback rather than adding to it.
+        assert!(!runs(&authorization, "/deploy", outsider()).await);
+        assert!(runs(&authorization, "/help", insider()).await);
+    }
+}
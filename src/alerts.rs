@@ -0,0 +1,477 @@
+// std.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Rocket.
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+
+// serde.
+use serde::Deserialize;
+
+// Regex.
+use regex::Regex;
+
+// logging.
+use log::{info, warn};
+
+// Own.
+use crate::adaptive_card::{
+    Action, AdaptiveCard, CardElement, Color, ContainerStyle, Size, Weight,
+};
+use crate::secret::constant_eq;
+use crate::types::{Attachment, MessageOut};
+use crate::WebexBotState;
+
+// ###################################################################
+// Alertmanager webhook payload (version 4). Grafana unified alerting
+// sends the same shape plus a few extra fields.
+// ###################################################################
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Alert {
+    pub status: String,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+    pub starts_at: String,
+    pub ends_at: String,
+    #[serde(rename = "generatorURL")]
+    pub generator_url: String,
+    pub fingerprint: String,
+    #[serde(rename = "dashboardURL")]
+    pub dashboard_url: String, // Grafana only.
+    pub value_string: String, // Grafana only.
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlertmanagerPayload {
+    pub version: String,
+    pub group_key: String,
+    pub status: String,
+    pub receiver: String,
+    pub group_labels: BTreeMap<String, String>,
+    pub common_labels: BTreeMap<String, String>,
+    pub common_annotations: BTreeMap<String, String>,
+    #[serde(rename = "externalURL")]
+    pub external_url: String,
+    pub alerts: Vec<Alert>,
+}
+
+// ###################################################################
+// Grafana webhook payload, both unified and legacy alerting.
+// ###################################################################
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GrafanaPayload {
+    pub title: String,
+    pub state: String,
+    pub message: String,
+    pub status: String,
+    pub alerts: Vec<Alert>,
+    pub common_labels: BTreeMap<String, String>,
+    pub rule_name: String,              // Legacy alerting only.
+    pub rule_url: String,               // Legacy alerting only.
+    pub tags: BTreeMap<String, String>, // Legacy alerting only.
+}
+
+impl GrafanaPayload {
+    // ------------------------------------------------------------------------------
+    // Legacy alerts carry no alert list; turn the rule itself into one.
+    // ------------------------------------------------------------------------------
+
+    fn into_alerts(self) -> Vec<Alert> {
+        if !self.alerts.is_empty() {
+            return self.alerts;
+        }
+
+        let mut labels = self.tags;
+        labels
+            .entry("alertname".to_string())
+            .or_insert(self.rule_name);
+        let status = match self.state.as_str() {
+            "ok" => "resolved",
+            _ => "firing",
+        };
+
+        vec![Alert {
+            status: status.to_string(),
+            labels,
+            annotations: BTreeMap::from([("summary".to_string(), self.message)]),
+            generator_url: self.rule_url,
+            ..Default::default()
+        }]
+    }
+}
+
+// ###################################################################
+// Label matcher, using the Alertmanager syntax: label=value, label!=value,
+// label=~regex and label!~regex.
+// ###################################################################
+
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Equal(String, String),
+    NotEqual(String, String),
+    Regex(String, Regex),
+    NotRegex(String, Regex),
+}
+
+impl Matcher {
+    pub fn parse(matcher: &str) -> Result<Matcher, String> {
+        let operators = ["=~", "!~", "!=", "="];
+        let (label, operator, value) = operators
+            .iter()
+            .find_map(|operator| {
+                matcher
+                    .split_once(operator)
+                    .map(|(label, value)| (label, *operator, value))
+            })
+            .ok_or_else(|| format!("Invalid matcher: {}", matcher))?;
+
+        let label = label.trim().to_string();
+        let value = value.trim().trim_matches('"').to_string();
+        let regex = || {
+            Regex::new(&format!("^(?:{})$", value))
+                .map_err(|e| format!("Invalid matcher {}: {}", matcher, e))
+        };
+
+        Ok(match operator {
+            "=~" => Matcher::Regex(label, regex()?),
+            "!~" => Matcher::NotRegex(label, regex()?),
+            "!=" => Matcher::NotEqual(label, value),
+            _ => Matcher::Equal(label, value),
+        })
+    }
+
+    // A missing label matches as an empty string, like in Alertmanager.
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        let value = |label: &String| labels.get(label).map(String::as_str).unwrap_or_default();
+        match self {
+            Matcher::Equal(label, expected) => value(label) == expected,
+            Matcher::NotEqual(label, expected) => value(label) != expected,
+            Matcher::Regex(label, regex) => regex.is_match(value(label)),
+            Matcher::NotRegex(label, regex) => !regex.is_match(value(label)),
+        }
+    }
+}
+
+// ###################################################################
+// Routing of the alerts to rooms.
+// ###################################################################
+
+struct AlertRoute {
+    matchers: Vec<Matcher>,
+    room_id: String,
+}
+
+/**
+ * An alert is posted to every room whose matchers all match its labels,
+ * or to the default room when no route matches.
+ */
+#[derive(Default)]
+pub struct AlertRouter {
+    routes: Vec<AlertRoute>,
+    default_room: Option<String>,
+    token: Option<String>,
+}
+
+impl AlertRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, matchers: &[&str], room_id: &str) -> Result<Self, String> {
+        let matchers = matchers
+            .iter()
+            .map(|matcher| Matcher::parse(matcher))
+            .collect::<Result<Vec<Matcher>, String>>()?;
+        self.routes.push(AlertRoute {
+            matchers,
+            room_id: room_id.to_string(),
+        });
+        Ok(self)
+    }
+
+    pub fn default_room(mut self, room_id: &str) -> Self {
+        self.default_room = Some(room_id.to_string());
+        self
+    }
+
+    // Require senders to authenticate with this bearer token.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Group the alerts by destination room.
+    // ------------------------------------------------------------------------------
+
+    fn dispatch(&self, alerts: Vec<Alert>) -> HashMap<String, Vec<Alert>> {
+        let mut rooms = HashMap::<String, Vec<Alert>>::new();
+        for alert in alerts {
+            let mut destinations = self
+                .routes
+                .iter()
+                .filter(|route| route.matchers.iter().all(|m| m.matches(&alert.labels)))
+                .map(|route| route.room_id.clone())
+                .collect::<Vec<String>>();
+            if destinations.is_empty() {
+                destinations.extend(self.default_room.clone());
+            }
+            // Post once per room, even when routes matching it are not adjacent.
+            let mut seen = HashSet::new();
+            destinations.retain(|room| seen.insert(room.clone()));
+
+            for room in destinations {
+                rooms.entry(room).or_default().push(alert.clone());
+            }
+        }
+        rooms
+    }
+}
+
+// ###################################################################
+// Request guard checking the optional bearer token.
+// ###################################################################
+
+pub(crate) struct AlertAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AlertAuth {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request.rocket().state::<AlertRouter>() {
+            Some(router) => router.token.as_deref(),
+            None => return Outcome::Failure((Status::NotFound, "Alert bridge disabled")),
+        };
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        let valid = |expected: &str| {
+            token.is_some_and(|token| constant_eq(token.as_bytes(), expected.as_bytes()))
+        };
+        match expected {
+            Some(expected) if !valid(expected) => {
+                warn!("Rejected alerts with an invalid token.");
+                Outcome::Failure((Status::Unauthorized, "Invalid token"))
+            }
+            _ => Outcome::Success(AlertAuth),
+        }
+    }
+}
+
+// ###################################################################
+// Adaptive card rendering.
+// ###################################################################
+
+const CARD_CONTENT_TYPE: &str = "application/vnd.microsoft.card.adaptive";
+
+fn severity_style(alert: &Alert) -> (Color, ContainerStyle) {
+    if alert.status == "resolved" {
+        return (Color::Good, ContainerStyle::Good);
+    }
+    match alert
+        .labels
+        .get("severity")
+        .map(|severity| severity.to_lowercase())
+        .as_deref()
+    {
+        Some("critical" | "error" | "page") => (Color::Attention, ContainerStyle::Attention),
+        Some("warning" | "warn") => (Color::Warning, ContainerStyle::Warning),
+        _ => (Color::Accent, ContainerStyle::Accent),
+    }
+}
+
+fn render_alert(alert: &Alert) -> CardElement {
+    let (color, style) = severity_style(alert);
+    let name = alert
+        .labels
+        .get("alertname")
+        .cloned()
+        .unwrap_or_else(|| "Alert".to_string());
+
+    let mut container = CardElement::container()
+        .set_container_style(style)
+        .add_element(
+            CardElement::text_block(format!("[{}] {}", alert.status.to_uppercase(), name))
+                .set_weight(Weight::Bolder)
+                .set_size(Size::Medium)
+                .set_color(color)
+                .set_wrap(true),
+        );
+
+    for annotation in ["summary", "description"] {
+        if let Some(text) = alert.annotations.get(annotation) {
+            container.add_element(CardElement::text_block(text).set_wrap(true));
+        }
+    }
+    if !alert.value_string.is_empty() {
+        container.add_element(
+            CardElement::text_block(&alert.value_string)
+                .set_subtle(true)
+                .set_wrap(true),
+        );
+    }
+
+    let mut facts = CardElement::fact_set();
+    for (label, value) in alert
+        .labels
+        .iter()
+        .filter(|(label, _)| *label != "alertname")
+    {
+        facts.add_key_value(label, value);
+    }
+    container.add_element(facts)
+}
+
+fn render_card(title: &str, alerts: &[Alert]) -> AdaptiveCard {
+    let mut card = AdaptiveCard::new();
+    card.add_body(
+        CardElement::text_block(title)
+            .set_weight(Weight::Bolder)
+            .set_size(Size::Large)
+            .set_wrap(true),
+    );
+    for alert in alerts {
+        card.add_body(render_alert(alert));
+    }
+
+    // Link to the source of the first alert that has one.
+    let link = alerts
+        .iter()
+        .map(|alert| &alert.generator_url)
+        .find(|url| !url.is_empty());
+    if let Some(url) = link {
+        card.add_action(Action::OpenUrl {
+            url: url.clone(),
+            title: Some("View source".to_string()),
+            style: None,
+        });
+    }
+    card.fallback_text = Some(title.to_string());
+    card
+}
+
+// ------------------------------------------------------------------------------
+// Post the alerts to their rooms. Returns the number of messages sent.
+// ------------------------------------------------------------------------------
+
+async fn forward(
+    state: &WebexBotState,
+    router: &AlertRouter,
+    title: &str,
+    alerts: Vec<Alert>,
+) -> usize {
+    let rooms = router.dispatch(alerts);
+    for (room_id, alerts) in &rooms {
+        info!(
+            "[Alerts]: posting {} alerts to room {}",
+            alerts.len(),
+            room_id
+        );
+        state
+            .client
+            .send_message(&MessageOut {
+                room_id: Some(room_id.clone()),
+                text: Some(title.to_string()),
+                attachments: Some(vec![Attachment {
+                    content_type: CARD_CONTENT_TYPE.to_string(),
+                    content: render_card(title, alerts),
+                }]),
+                ..Default::default()
+            })
+            .await;
+    }
+    rooms.len()
+}
+
+// #########################################################################################
+// Alertmanager receiver.
+// #########################################################################################
+
+#[post("/alerts/alertmanager", format = "json", data = "<data>")]
+pub(crate) async fn alertmanager(
+    _auth: AlertAuth,
+    data: Json<AlertmanagerPayload>,
+    router: &State<AlertRouter>,
+    state: &State<WebexBotState>,
+) -> Status {
+    let payload = data.into_inner();
+    let name = payload
+        .common_labels
+        .get("alertname")
+        .or(payload.group_labels.get("alertname"))
+        .cloned()
+        .unwrap_or_else(|| payload.receiver.clone());
+    let title = format!(
+        "{} {}: {} alert(s)",
+        payload.status.to_uppercase(),
+        name,
+        payload.alerts.len()
+    );
+
+    forward(state, router, &title, payload.alerts).await;
+    Status::Ok
+}
+
+// #########################################################################################
+// Grafana receiver.
+// #########################################################################################
+
+#[post("/alerts/grafana", format = "json", data = "<data>")]
+pub(crate) async fn grafana(
+    _auth: AlertAuth,
+    data: Json<GrafanaPayload>,
+    router: &State<AlertRouter>,
+    state: &State<WebexBotState>,
+) -> Status {
+    let payload = data.into_inner();
+    let title = if payload.title.is_empty() {
+        format!("Grafana: {}", payload.rule_name)
+    } else {
+        payload.title.clone()
+    };
+
+    forward(state, router, &title, payload.into_alerts()).await;
+    Status::Ok
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_are_posted_once_per_room() {
+        let router = AlertRouter::new()
+            .route(&["team=db"], "ops")
+            .unwrap()
+            .route(&["team=web"], "web")
+            .unwrap()
+            .route(&["severity=critical"], "ops")
+            .unwrap();
+        let alert = Alert {
+            labels: BTreeMap::from([
+                ("team".to_string(), "db".to_string()),
+                ("severity".to_string(), "critical".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let rooms = router.dispatch(vec![alert]);
+        assert_eq!(rooms.len(), 1);
+        assert_eq!(rooms["ops"].len(), 1);
+    }
+}
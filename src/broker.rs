@@ -0,0 +1,395 @@
+// std.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+// serde.
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+// Rocket.
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request as HttpRequest};
+use rocket::serde::json::Json;
+use rocket::{delete, post, tokio, State};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+// Websocket.
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// Future.
+use futures_util::{SinkExt, StreamExt};
+
+// logging.
+use log::{debug, error, info, warn};
+
+// Own.
+use crate::device_auth::{BrokerAuth, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use crate::devices::DeviceRegistry;
+use crate::reconnect::wait_for_shutdown;
+use crate::shutdown::Shutdown;
+use crate::types::{Publish, Register, RegisterResponse};
+
+// Sent by a connected client to change its groups.
+#[derive(Deserialize)]
+struct Subscription {
+    groups: Vec<String>,
+}
+
+// ###################################################################
+// Pub/sub broker for the embedded devices, speaking the protocol of
+// `WebSocketClient`: `POST /register`, `POST /publish` and
+// `DELETE /register/<token>` on the bot server, websocket endpoints
+// (`/ws/<token>`, one per registration) on their own listener.
+// ###################################################################
+
+#[derive(Clone)]
+pub struct Broker {
+    bind: SocketAddr,   // Address the websocket listener binds to.
+    public_url: String, // Base of the endpoint URLs handed out, e.g. "ws://host:8081".
+    queue: usize,       // Messages buffered per client before dropping new ones.
+    auth: BrokerAuth,   // Required on register, unregister and publish.
+    registry: DeviceRegistry,
+}
+
+impl Broker {
+    pub fn new(bind: SocketAddr) -> Self {
+        Broker {
+            bind,
+            public_url: format!("ws://{}", bind),
+            queue: 64,
+            auth: BrokerAuth::None,
+            registry: DeviceRegistry::new(),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // URL the devices reach the listener at, when it differs from the bind address
+    // (e.g. behind a proxy or when binding 0.0.0.0).
+    // ------------------------------------------------------------------------------
+
+    pub fn public_url(mut self, url: &str) -> Self {
+        self.public_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn queue(mut self, queue: usize) -> Self {
+        self.queue = queue.max(1);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Require the devices to authenticate their register, unregister and publish
+    // requests.
+    // Behind a TLS terminating proxy, hand out wss:// urls through `public_url`.
+    // ------------------------------------------------------------------------------
+
+    pub fn auth(mut self, auth: BrokerAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Devices registered with the broker and their presence.
+    // ------------------------------------------------------------------------------
+
+    pub fn registry(&self) -> &DeviceRegistry {
+        &self.registry
+    }
+
+    // ------------------------------------------------------------------------------
+    // Register a client, returning the URL of its websocket endpoint.
+    // ------------------------------------------------------------------------------
+
+    pub fn register(
+        &self,
+        user_id: u16,
+        groups: Vec<String>,
+        metadata: HashMap<String, String>,
+    ) -> String {
+        let token = self.registry.register(user_id, groups, metadata);
+        format!("{}/ws/{}", self.public_url, token)
+    }
+
+    pub fn unregister(&self, token: &str) -> bool {
+        self.registry.unregister(token)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Send a message to every connected client subscribed to the group. Returns the
+    // number of clients it was queued for.
+    // ------------------------------------------------------------------------------
+
+    pub fn publish(&self, group: &str, message: &str) -> usize {
+        self.registry.publish(group, message)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Accept the websocket connections until the shutdown.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) async fn serve(self, shutdown: Option<Shutdown>) {
+        let listener = match TcpListener::bind(self.bind).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Could not bind the broker to {}: {}", self.bind, e);
+                return;
+            }
+        };
+        info!("Broker listening on {}", self.bind);
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(self.clone().handle(stream, peer, shutdown.clone()));
+                    }
+                    Err(e) => warn!("Broker could not accept a connection: {}", e),
+                },
+                _ = wait_for_shutdown(&shutdown) => break,
+            }
+        }
+    }
+
+    async fn handle(self, stream: TcpStream, peer: SocketAddr, shutdown: Option<Shutdown>) {
+        // Only the endpoints handed out by `register` are accepted.
+        let mut token = None;
+        #[allow(clippy::result_large_err)] // The error type is imposed by tungstenite.
+        let accept = |request: &Request, response: Response| {
+            let path = request.uri().path();
+            match path.strip_prefix("/ws/") {
+                Some(t) if self.registry.contains(t) => {
+                    token = Some(t.to_string());
+                    Ok(response)
+                }
+                _ => {
+                    let mut error = ErrorResponse::new(Some("Unknown endpoint".to_string()));
+                    *error.status_mut() = StatusCode::NOT_FOUND;
+                    Err(error)
+                }
+            }
+        };
+        let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, accept).await {
+            Ok(ws_stream) => ws_stream,
+            Err(e) => {
+                debug!("Refused the websocket of {}: {}", peer, e);
+                return;
+            }
+        };
+        let Some(token) = token else {
+            return;
+        };
+
+        let (sender, mut receiver) = mpsc::channel(self.queue);
+        let Some(user_id) = self.registry.connected(&token, sender) else {
+            return;
+        };
+        info!("Device {} connected on {} from {}", user_id, token, peer);
+
+        let (mut sink, mut frames) = ws_stream.split();
+        loop {
+            tokio::select! {
+                outgoing = receiver.recv() => match outgoing {
+                    Some(message) => {
+                        if sink.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Unregistered.
+                    None => break,
+                },
+                frame = frames.next() => {
+                    self.registry.seen(&token);
+                    match frame {
+                        Some(Ok(Message::Text(text))) => self.subscribe(&token, &text),
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => (),
+                    }
+                }
+                _ = wait_for_shutdown(&shutdown) => break,
+            }
+        }
+
+        let _ = sink.close().await;
+        self.registry.disconnected(&token);
+        info!("Device {} disconnected from {}", user_id, token);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Connected clients change their groups with `{"groups": [...]}`.
+    // ------------------------------------------------------------------------------
+
+    fn subscribe(&self, token: &str, text: &str) {
+        let Ok(subscription) = serde_json::from_str::<Subscription>(text) else {
+            debug!("Ignoring a message from {}: {}", token, text);
+            return;
+        };
+        self.registry.subscribe(token, subscription.groups);
+    }
+}
+
+// ###################################################################
+// Broker protocol routes.
+// ###################################################################
+
+// Authentication headers, checked against the request line and the body by the
+// routes.
+pub(crate) struct AuthHeaders<'r> {
+    method: &'r str,
+    path: &'r str,
+    authorization: Option<&'r str>,
+    timestamp: Option<&'r str>,
+    signature: Option<&'r str>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthHeaders<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r HttpRequest<'_>) -> Outcome<Self, ()> {
+        let headers = request.headers();
+        Outcome::Success(AuthHeaders {
+            method: request.method().as_str(),
+            path: request.uri().path().as_str(),
+            authorization: headers.get_one("Authorization"),
+            timestamp: headers.get_one(TIMESTAMP_HEADER),
+            signature: headers.get_one(SIGNATURE_HEADER),
+        })
+    }
+}
+
+impl Broker {
+    // Check the credentials of a request against its method, path and body.
+    fn authenticate(&self, auth: &AuthHeaders, body: &str) -> Result<(), Status> {
+        let verified = self.auth.verify(
+            auth.method,
+            auth.path,
+            auth.authorization,
+            auth.timestamp,
+            auth.signature,
+            body.as_bytes(),
+        );
+        if !verified {
+            warn!("Rejected a broker request with invalid credentials.");
+            return Err(Status::Unauthorized);
+        }
+        Ok(())
+    }
+
+    // Decode the body of an authenticated request.
+    fn accept<T: DeserializeOwned>(&self, auth: &AuthHeaders, body: &str) -> Result<T, Status> {
+        self.authenticate(auth, body)?;
+        serde_json::from_str(body).map_err(|_| Status::BadRequest)
+    }
+}
+
+#[post("/register", format = "json", data = "<body>")]
+pub(crate) fn register(
+    auth: AuthHeaders<'_>,
+    body: &str,
+    broker: &State<Broker>,
+) -> Result<Json<RegisterResponse>, Status> {
+    let register: Register = broker.accept(&auth, body)?;
+    Ok(Json(RegisterResponse {
+        url: broker.register(register.user_id, register.groups, register.metadata),
+    }))
+}
+
+// Signed over an empty body, as the request has none. The token is in the
+// signed path, so a signature can't unregister another device.
+#[delete("/register/<token>")]
+pub(crate) fn unregister(auth: AuthHeaders<'_>, token: &str, broker: &State<Broker>) -> Status {
+    if let Err(status) = broker.authenticate(&auth, "") {
+        return status;
+    }
+    match broker.unregister(token) {
+        true => Status::Ok,
+        false => Status::NotFound,
+    }
+}
+
+#[post("/publish", format = "json", data = "<body>")]
+pub(crate) fn publish(auth: AuthHeaders<'_>, body: &str, broker: &State<Broker>) -> Status {
+    let publish: Publish = match broker.accept(&auth, body) {
+        Ok(publish) => publish,
+        Err(status) => return status,
+    };
+    let delivered = broker.publish(&publish.group, &publish.message);
+    debug!(
+        "Device {} published to {} ({} clients)",
+        publish.user_id, publish.group, delivered
+    );
+    Status::Ok
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::{Client, LocalRequest};
+
+    #[rocket::async_test]
+    async fn unregister_requires_the_credentials() {
+        let broker = Broker::new("127.0.0.1:0".parse().unwrap())
+            .auth(BrokerAuth::Bearer("secret".to_string()));
+        let token = broker.registry().register(1, Vec::new(), HashMap::new());
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![unregister])
+            .manage(broker);
+        let client = Client::untracked(rocket).await.unwrap();
+
+        let uri = format!("/register/{}", token);
+        let response = client.delete(uri.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .delete(uri)
+            .header(Header::new("Authorization", "Bearer secret"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn unregister_signatures_are_bound_to_the_token() {
+        let auth = BrokerAuth::Hmac("secret".to_string());
+        let broker = Broker::new("127.0.0.1:0".parse().unwrap()).auth(auth.clone());
+        let first = broker.registry().register(1, Vec::new(), HashMap::new());
+        let second = broker.registry().register(2, Vec::new(), HashMap::new());
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![unregister])
+            .manage(broker);
+        let client = Client::untracked(rocket).await.unwrap();
+
+        // The signature made for the first token doesn't unregister the second.
+        let signed_for = |token: &str| auth.headers("DELETE", &format!("/register/{}", token), b"");
+        let request = client.delete(format!("/register/{}", second));
+        let response = with_headers(request, signed_for(&first)).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        for token in [&first, &second] {
+            let request = client.delete(format!("/register/{}", token));
+            let response = with_headers(request, signed_for(token)).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+    }
+
+    fn with_headers<'c>(
+        mut request: LocalRequest<'c>,
+        headers: reqwest::header::HeaderMap,
+    ) -> LocalRequest<'c> {
+        for (name, value) in headers.iter() {
+            request = request.header(Header::new(
+                name.as_str().to_string(),
+                value.to_str().unwrap().to_string(),
+            ));
+        }
+        request
+    }
+}
@@ -0,0 +1,160 @@
+// std.
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// serde.
+use serde_json::Value;
+
+// Tokio.
+use rocket::tokio::time;
+
+// logging.
+use log::warn;
+
+// Own.
+use crate::store::{now, StateStore};
+
+// ###################################################################
+// Record of the events already processed.
+// ###################################################################
+
+/**
+ * Webex may deliver the same event more than once. Every event id is
+ * written to the state store for a time window, so duplicates are dropped
+ * across replicas sharing the store. The most recent ids are also kept in a
+ * bounded local cache to skip the store on quick redeliveries. The store is
+ * awaited on the request path, so a slow backend is given up on after
+ * `STORE_TIMEOUT` and the event let through.
+ */
+pub(crate) struct Deduplicator {
+    window: Duration,      // How long an event id is remembered.
+    capacity: usize,       // Ids kept in the local cache.
+    recent: Mutex<Recent>, // Local cache of the latest ids.
+}
+
+#[derive(Default)]
+struct Recent {
+    ids: HashSet<String>,
+    order: VecDeque<(String, i64)>, // Id and when it was seen.
+}
+
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Deduplicator::new(Duration::from_secs(60 * 60), 10_000)
+    }
+}
+
+impl Deduplicator {
+    const NAMESPACE: &'static str = "webhook_events";
+    const STORE_TIMEOUT: Duration = Duration::from_millis(500);
+
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        Deduplicator {
+            window,
+            capacity,
+            recent: Mutex::new(Recent::default()),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Record the event, returning false when it was already processed. Events are
+    // let through when the store fails: a duplicate beats a lost message.
+    // ------------------------------------------------------------------------------
+
+    pub async fn first_delivery(&self, store: &dyn StateStore, key: &str) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+
+        // Claim the id locally first, so concurrent redeliveries don't reach the
+        // store.
+        let seen_at = now();
+        {
+            let mut recent = self.recent.lock().unwrap();
+            self.evict(&mut recent, seen_at);
+            if !recent.ids.insert(key.to_string()) {
+                return false;
+            }
+            recent.order.push_back((key.to_string(), seen_at));
+            self.evict(&mut recent, seen_at);
+        }
+
+        let recorded = store.insert_if_absent(
+            Self::NAMESPACE,
+            key,
+            Value::from(seen_at),
+            Some(self.window),
+        );
+        match time::timeout(Self::STORE_TIMEOUT, recorded).await {
+            Ok(Ok(inserted)) => inserted,
+            Ok(Err(e)) => {
+                warn!("Could not record the event {}: {}", key, e);
+                true
+            }
+            Err(_) => {
+                warn!("Timed out recording the event {}", key);
+                true
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Forget an event that could not be processed, so its redelivery goes through.
+    // ------------------------------------------------------------------------------
+
+    pub async fn forget(&self, store: &dyn StateStore, key: &str) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.ids.remove(key) {
+                recent.order.retain(|(id, _)| id != key);
+            }
+        }
+        if let Err(e) = store.delete(Self::NAMESPACE, key).await {
+            warn!("Could not forget the event {}: {}", key, e);
+        }
+    }
+
+    fn evict(&self, recent: &mut Recent, now: i64) {
+        let window = i64::try_from(self.window.as_millis()).unwrap_or(i64::MAX);
+        while let Some((id, seen_at)) = recent.order.front() {
+            if recent.order.len() <= self.capacity && now.saturating_sub(*seen_at) < window {
+                break;
+            }
+            recent.ids.remove(id);
+            recent.order.pop_front();
+        }
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[rocket::async_test]
+    async fn redeliveries_are_dropped_until_forgotten() {
+        let store = MemoryStore::new();
+        let dedup = Deduplicator::default();
+
+        assert!(dedup.first_delivery(&store, "event").await);
+        assert!(!dedup.first_delivery(&store, "event").await);
+
+        dedup.forget(&store, "event").await;
+        assert!(dedup.first_delivery(&store, "event").await);
+    }
+
+    #[rocket::async_test]
+    async fn the_store_catches_what_the_cache_evicted() {
+        let store = MemoryStore::new();
+        let dedup = Deduplicator::new(Duration::from_secs(60), 1);
+
+        assert!(dedup.first_delivery(&store, "first").await);
+        assert!(dedup.first_delivery(&store, "second").await);
+        assert!(!dedup.first_delivery(&store, "first").await);
+    }
+}
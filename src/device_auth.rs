@@ -0,0 +1,297 @@
+// std.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Crypto.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Reqwest.
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+
+// Own.
+use crate::secret::constant_eq;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const TIMESTAMP_HEADER: &str = "x-broker-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-broker-signature";
+
+// Signed requests older (or newer) than this are refused. A captured request
+// can be replayed as is until then, so keep it short.
+const MAX_SKEW_SECS: u64 = 60;
+
+// ###################################################################
+// Authentication of the broker protocol requests (register,
+// unregister and publish), shared by `WebSocketClient` and the broker.
+// ###################################################################
+
+/**
+ * `Bearer` sends the token in the `Authorization` header. `Hmac` signs
+ * every request with the shared secret: `X-Broker-Timestamp` carries the
+ * unix time and `X-Broker-Signature` the hex HMAC-SHA256 of
+ * `"<METHOD>\n<path>\n<timestamp>\n<body>"`. The request can't be altered
+ * nor sent to another route, and is refused once `MAX_SKEW_SECS` have
+ * passed; within that window the very same request could still be replayed.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BrokerAuth {
+    #[default]
+    None,
+    Bearer(String), // Shared token.
+    Hmac(String),   // Shared secret.
+}
+
+impl BrokerAuth {
+    // ------------------------------------------------------------------------------
+    // Headers authenticating a request with the given method, path (e.g.
+    // "/register") and body.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn headers(&self, method: &str, path: &str, body: &[u8]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        match self {
+            BrokerAuth::None => (),
+            BrokerAuth::Bearer(token) => {
+                if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", token)) {
+                    headers.insert(AUTHORIZATION, value);
+                }
+            }
+            BrokerAuth::Hmac(secret) => {
+                let timestamp = now().to_string();
+                let signature = to_hex(
+                    &mac(secret, method, path, &timestamp, body)
+                        .finalize()
+                        .into_bytes(),
+                );
+                headers.insert(
+                    HeaderName::from_static(TIMESTAMP_HEADER),
+                    HeaderValue::from_str(&timestamp).unwrap(),
+                );
+                headers.insert(
+                    HeaderName::from_static(SIGNATURE_HEADER),
+                    HeaderValue::from_str(&signature).unwrap(),
+                );
+            }
+        }
+        headers
+    }
+
+    // ------------------------------------------------------------------------------
+    // Check the headers of a received request, for brokers implementing the
+    // protocol. Comparisons are constant time.
+    // ------------------------------------------------------------------------------
+
+    pub fn verify(
+        &self,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        timestamp: Option<&str>,
+        signature: Option<&str>,
+        body: &[u8],
+    ) -> bool {
+        match self {
+            BrokerAuth::None => true,
+            BrokerAuth::Bearer(token) => authorization
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .is_some_and(|given| constant_eq(given.as_bytes(), token.as_bytes())),
+            BrokerAuth::Hmac(secret) => {
+                let (Some(timestamp), Some(signature)) = (timestamp, signature) else {
+                    return false;
+                };
+                let Ok(sent_at) = timestamp.parse::<u64>() else {
+                    return false;
+                };
+                if now().abs_diff(sent_at) > MAX_SKEW_SECS {
+                    return false;
+                }
+                let Some(signature) = from_hex(signature) else {
+                    return false;
+                };
+                mac(secret, method, path, timestamp, body)
+                    .verify_slice(&signature)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+fn mac(secret: &str, method: &str, path: &str, timestamp: &str, body: &[u8]) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    for part in [method, path, timestamp] {
+        mac.update(part.as_bytes());
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = br#"{"user_id":1}"#;
+
+    fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+        to_hex(
+            &mac(secret, "POST", "/register", timestamp, body)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    #[test]
+    fn bearer_tokens_are_checked() {
+        let auth = BrokerAuth::Bearer("token".to_string());
+        let headers = auth.headers("POST", "/register", BODY);
+        let authorization = header(&headers, "authorization");
+        assert_eq!(authorization, Some("Bearer token"));
+
+        assert!(auth.verify("POST", "/register", authorization, None, None, BODY));
+        for given in [None, Some("Bearer other"), Some("token"), Some("Bearer ")] {
+            assert!(!auth.verify("POST", "/register", given, None, None, BODY));
+        }
+    }
+
+    #[test]
+    fn hmac_signatures_round_trip() {
+        let auth = BrokerAuth::Hmac("secret".to_string());
+        let headers = auth.headers("POST", "/register", BODY);
+        let timestamp = header(&headers, TIMESTAMP_HEADER);
+        let signature = header(&headers, SIGNATURE_HEADER);
+
+        assert!(auth.verify("POST", "/register", None, timestamp, signature, BODY));
+        assert!(!auth.verify("POST", "/register", None, timestamp, None, BODY));
+        assert!(!auth.verify("POST", "/register", None, None, signature, BODY));
+
+        let other = BrokerAuth::Hmac("other".to_string());
+        assert!(!other.verify("POST", "/register", None, timestamp, signature, BODY));
+    }
+
+    #[test]
+    fn hmac_signatures_cover_the_request() {
+        let auth = BrokerAuth::Hmac("secret".to_string());
+        let headers = auth.headers("POST", "/register", BODY);
+        let timestamp = header(&headers, TIMESTAMP_HEADER);
+        let signature = header(&headers, SIGNATURE_HEADER);
+
+        let tampered = br#"{"user_id":2}"#;
+        assert!(!auth.verify("POST", "/register", None, timestamp, signature, tampered));
+        assert!(!auth.verify("POST", "/publish", None, timestamp, signature, BODY));
+        assert!(!auth.verify("DELETE", "/register", None, timestamp, signature, BODY));
+    }
+
+    #[test]
+    fn stale_signatures_are_refused() {
+        let auth = BrokerAuth::Hmac("secret".to_string());
+        for timestamp in [now() - MAX_SKEW_SECS - 1, now() + MAX_SKEW_SECS + 1] {
+            let timestamp = timestamp.to_string();
+            let signature = sign("secret", &timestamp, BODY);
+            assert!(!auth.verify(
+                "POST",
+                "/register",
+                None,
+                Some(&timestamp),
+                Some(&signature),
+                BODY
+            ));
+        }
+
+        let timestamp = (now() - MAX_SKEW_SECS + 5).to_string();
+        let signature = sign("secret", &timestamp, BODY);
+        assert!(auth.verify(
+            "POST",
+            "/register",
+            None,
+            Some(&timestamp),
+            Some(&signature),
+            BODY
+        ));
+        assert!(!auth.verify(
+            "POST",
+            "/register",
+            None,
+            Some("yesterday"),
+            Some(&signature),
+            BODY
+        ));
+    }
+
+    #[test]
+    fn malformed_signatures_are_refused() {
+        let auth = BrokerAuth::Hmac("secret".to_string());
+        let timestamp = now().to_string();
+        let signature = sign("secret", &timestamp, BODY);
+
+        let odd = &signature[1..];
+        let non_hex = format!("zz{}", &signature[2..]);
+        let upper = signature.to_uppercase();
+        let truncated = &signature[..signature.len() - 2];
+        for given in [odd, non_hex.as_str(), truncated, ""] {
+            assert!(!auth.verify(
+                "POST",
+                "/register",
+                None,
+                Some(&timestamp),
+                Some(given),
+                BODY
+            ));
+        }
+        // Hex digits are case insensitive.
+        assert!(auth.verify(
+            "POST",
+            "/register",
+            None,
+            Some(&timestamp),
+            Some(&upper),
+            BODY
+        ));
+    }
+
+    #[test]
+    fn hex_decoding() {
+        assert_eq!(from_hex("00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("0é0"), None);
+    }
+
+    #[test]
+    fn no_auth_accepts_everything() {
+        assert!(BrokerAuth::None
+            .headers("POST", "/register", BODY)
+            .is_empty());
+        assert!(BrokerAuth::None.verify("POST", "/register", None, None, None, BODY));
+    }
+}
@@ -0,0 +1,521 @@
+// std.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// chrono.
+use chrono::{DateTime, Utc};
+
+// serde.
+use serde::Serialize;
+
+// Regex.
+use regex::Regex;
+
+// Tokio.
+use rocket::tokio::sync::mpsc::Sender;
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+// logging.
+use log::{debug, info, warn};
+
+// uuid.
+use uuid::Uuid;
+
+// Own.
+use crate::types::{ArgTuple, HandlerResult, Message, MessageOut};
+use crate::WebexClient;
+
+// ###################################################################
+// Device known to the broker, with its presence.
+// ###################################################################
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub user_id: u16,
+    pub groups: Vec<String>, // Groups of the latest registration or subscription.
+    pub online: bool,        // At least one of its endpoints is connected.
+    pub connected_at: Option<DateTime<Utc>>, // Start of the current or last connection.
+    pub disconnected_at: Option<DateTime<Utc>>, // End of the last connection.
+    pub last_seen: Option<DateTime<Utc>>, // Last frame received from the device.
+    pub metadata: HashMap<String, String>, // Sent at registration or set by the bot.
+}
+
+// Websocket endpoint handed out to a registration.
+struct Endpoint {
+    user_id: u16,
+    sender: Option<Sender<WsMessage>>, // Set while the endpoint is connected.
+}
+
+#[derive(Default)]
+struct Devices {
+    endpoints: HashMap<String, Endpoint>, // By endpoint token.
+    devices: HashMap<u16, Device>,        // By user id.
+}
+
+impl Devices {
+    fn is_online(&self, user_id: u16) -> bool {
+        self.endpoints
+            .values()
+            .any(|endpoint| endpoint.user_id == user_id && endpoint.sender.is_some())
+    }
+}
+
+// ###################################################################
+// Registry of the devices registered with the broker, shared with the
+// handlers through `client.devices`.
+// ###################################################################
+
+#[derive(Clone, Default)]
+pub struct DeviceRegistry {
+    inner: Arc<Mutex<Devices>>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Known devices, online or not, by user id.
+    // ------------------------------------------------------------------------------
+
+    pub fn list(&self) -> Vec<Device> {
+        let mut devices: Vec<Device> = self
+            .inner
+            .lock()
+            .unwrap()
+            .devices
+            .values()
+            .cloned()
+            .collect();
+        devices.sort_by_key(|device| device.user_id);
+        devices
+    }
+
+    pub fn get(&self, user_id: u16) -> Option<Device> {
+        self.inner.lock().unwrap().devices.get(&user_id).cloned()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Online devices subscribed to the group.
+    // ------------------------------------------------------------------------------
+
+    pub fn online(&self, group: &str) -> Vec<Device> {
+        self.list()
+            .into_iter()
+            .filter(|device| device.online && device.groups.iter().any(|g| g == group))
+            .collect()
+    }
+
+    pub fn set_metadata(&self, user_id: u16, key: &str, value: &str) -> bool {
+        match self.inner.lock().unwrap().devices.get_mut(&user_id) {
+            Some(device) => {
+                device.metadata.insert(key.to_string(), value.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Send a message to every online device of the group. Returns the number of
+    // endpoints it was queued for.
+    // ------------------------------------------------------------------------------
+
+    pub fn publish(&self, group: &str, message: &str) -> usize {
+        self.send_where(message, |device| device.groups.iter().any(|g| g == group))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Send a message to a single device, on all of its connected endpoints.
+    // ------------------------------------------------------------------------------
+
+    pub fn send(&self, user_id: u16, message: &str) -> usize {
+        self.send_where(message, |device| device.user_id == user_id)
+    }
+
+    fn send_where<F: Fn(&Device) -> bool>(&self, message: &str, filter: F) -> usize {
+        let inner = self.inner.lock().unwrap();
+        let mut delivered = 0;
+        for (token, endpoint) in inner.endpoints.iter() {
+            let Some(sender) = endpoint.sender.as_ref() else {
+                continue;
+            };
+            if !inner.devices.get(&endpoint.user_id).is_some_and(&filter) {
+                continue;
+            }
+            match sender.try_send(WsMessage::Text(message.to_string())) {
+                Ok(()) => delivered += 1,
+                Err(e) => warn!("Dropping a message to {}: {}", token, e),
+            }
+        }
+        debug!("Message queued for {} endpoints", delivered);
+        delivered
+    }
+
+    // ------------------------------------------------------------------------------
+    // Broker side: registrations and connections.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn register(
+        &self,
+        user_id: u16,
+        groups: Vec<String>,
+        metadata: HashMap<String, String>,
+    ) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let mut inner = self.inner.lock().unwrap();
+        // Clients register again to reconnect, drop the endpoints they left idle.
+        inner
+            .endpoints
+            .retain(|_, endpoint| endpoint.user_id != user_id || endpoint.sender.is_some());
+        inner.endpoints.insert(
+            token.clone(),
+            Endpoint {
+                user_id,
+                sender: None,
+            },
+        );
+
+        let online = inner.is_online(user_id);
+        let device = inner.devices.entry(user_id).or_insert_with(|| Device {
+            user_id,
+            groups: Vec::new(),
+            online,
+            connected_at: None,
+            disconnected_at: None,
+            last_seen: None,
+            metadata: HashMap::new(),
+        });
+        device.groups = groups;
+        device.metadata.extend(metadata);
+        info!("Registered the device {} on {}", user_id, token);
+        token
+    }
+
+    pub(crate) fn unregister(&self, token: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(endpoint) = inner.endpoints.remove(token) else {
+            return false;
+        };
+        // Forget the device with its last endpoint.
+        let user_id = endpoint.user_id;
+        if !inner.endpoints.values().any(|e| e.user_id == user_id) {
+            inner.devices.remove(&user_id);
+        } else if endpoint.sender.is_some() {
+            let online = inner.is_online(user_id);
+            if let Some(device) = inner.devices.get_mut(&user_id) {
+                device.online = online;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn contains(&self, token: &str) -> bool {
+        self.inner.lock().unwrap().endpoints.contains_key(token)
+    }
+
+    pub(crate) fn connected(&self, token: &str, sender: Sender<WsMessage>) -> Option<u16> {
+        let mut inner = self.inner.lock().unwrap();
+        let endpoint = inner.endpoints.get_mut(token)?;
+        endpoint.sender = Some(sender);
+        let user_id = endpoint.user_id;
+
+        let now = Utc::now();
+        if let Some(device) = inner.devices.get_mut(&user_id) {
+            if !device.online {
+                device.connected_at = Some(now);
+            }
+            device.online = true;
+            device.last_seen = Some(now);
+        }
+        Some(user_id)
+    }
+
+    pub(crate) fn disconnected(&self, token: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(endpoint) = inner.endpoints.get_mut(token) else {
+            return;
+        };
+        endpoint.sender = None;
+        let user_id = endpoint.user_id;
+
+        let online = inner.is_online(user_id);
+        if let Some(device) = inner.devices.get_mut(&user_id) {
+            if device.online && !online {
+                device.disconnected_at = Some(Utc::now());
+            }
+            device.online = online;
+        }
+    }
+
+    pub(crate) fn seen(&self, token: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(user_id) = inner.endpoints.get(token).map(|endpoint| endpoint.user_id) else {
+            return;
+        };
+        if let Some(device) = inner.devices.get_mut(&user_id) {
+            device.last_seen = Some(Utc::now());
+        }
+    }
+
+    pub(crate) fn subscribe(&self, token: &str, groups: Vec<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(user_id) = inner.endpoints.get(token).map(|endpoint| endpoint.user_id) else {
+            return;
+        };
+        if let Some(device) = inner.devices.get_mut(&user_id) {
+            device.groups = groups;
+        }
+    }
+}
+
+// ###################################################################
+// Built-in `devices` command, opt-in through
+// `WebexBotServer::enable_devices_command`.
+// ###################################################################
+
+pub const COMMAND: &str = "devices";
+
+// Priority of the devices pattern, ahead of the user defined patterns.
+pub(crate) const PRIORITY: i32 = 1000;
+
+// Anchored at the start of the text, which follows the bot mention in group spaces.
+pub(crate) fn pattern() -> Regex {
+    Regex::new(r"(?i)^\s*/?devices(?:\s+(?P<group>\S+))?\s*$").unwrap()
+}
+
+pub(crate) fn list_devices(
+    client: WebexClient,
+    message: Message,
+    required_arguments: ArgTuple,
+    _optional_arguments: ArgTuple,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send + Sync + 'static>> {
+    Box::pin(async move {
+        let group = required_arguments
+            .iter()
+            .find(|(name, value)| name == "group" && !value.is_empty())
+            .map(|(_, value)| value.clone());
+
+        let devices: Vec<Device> = client
+            .devices
+            .list()
+            .into_iter()
+            .filter(|device| group.as_ref().is_none_or(|g| device.groups.contains(g)))
+            .collect();
+
+        let markdown = match (devices.is_empty(), &group) {
+            (true, Some(group)) => format!("No device registered in `{}`.", group),
+            (true, None) => "No device registered.".to_string(),
+            _ => devices
+                .iter()
+                .map(format_device)
+                .collect::<Vec<String>>()
+                .join("\n"),
+        };
+
+        client
+            .send_message(&MessageOut {
+                room_id: message.room_id.clone(),
+                parent_id: message.parent_id.clone().or(message.id.clone()),
+                markdown: Some(markdown),
+                ..Default::default()
+            })
+            .await;
+        Ok(())
+    })
+}
+
+fn format_device(device: &Device) -> String {
+    let presence = match (device.online, device.last_seen) {
+        (true, _) => "online".to_string(),
+        (false, Some(last_seen)) => format!("offline, last seen {}", last_seen.format("%F %T UTC")),
+        (false, None) => "never connected".to_string(),
+    };
+    let mut line = format!(
+        "- **{}** ({}) groups: {}",
+        device.user_id,
+        presence,
+        device.groups.join(", ")
+    );
+    if !device.metadata.is_empty() {
+        let mut metadata: Vec<String> = device
+            .metadata
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        metadata.sort();
+        line.push_str(&format!(" [{}]", metadata.join(", ")));
+    }
+    line
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use rocket::tokio::sync::mpsc::{self, Receiver};
+
+    fn groups(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn connect(registry: &DeviceRegistry, token: &str) -> Receiver<WsMessage> {
+        let (sender, receiver) = mpsc::channel(8);
+        registry.connected(token, sender);
+        receiver
+    }
+
+    #[test]
+    fn devices_go_online_and_offline_with_their_connection() {
+        let registry = DeviceRegistry::new();
+        let token = registry.register(1, groups(&["lab"]), HashMap::new());
+        let device = registry.get(1).unwrap();
+        assert!(!device.online);
+        assert!(device.connected_at.is_none() && device.last_seen.is_none());
+        assert!(registry.online("lab").is_empty());
+
+        let mut receiver = connect(&registry, &token);
+        let device = registry.get(1).unwrap();
+        assert!(device.online);
+        assert!(device.connected_at.is_some() && device.disconnected_at.is_none());
+        assert_eq!(device.last_seen, device.connected_at);
+        assert_eq!(registry.online("lab"), vec![device]);
+
+        assert_eq!(registry.publish("lab", "hello"), 1);
+        assert_eq!(registry.publish("other", "hello"), 0);
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            WsMessage::Text("hello".to_string())
+        );
+
+        registry.disconnected(&token);
+        let device = registry.get(1).unwrap();
+        assert!(!device.online);
+        assert!(device.disconnected_at.is_some());
+        assert!(registry.online("lab").is_empty());
+        assert_eq!(registry.publish("lab", "hello"), 0);
+    }
+
+    #[test]
+    fn online_devices_are_filtered_by_group() {
+        let registry = DeviceRegistry::new();
+        let first = registry.register(1, groups(&["lab", "office"]), HashMap::new());
+        let second = registry.register(2, groups(&["office"]), HashMap::new());
+        registry.register(3, groups(&["lab"]), HashMap::new());
+        let _first = connect(&registry, &first);
+        let _second = connect(&registry, &second);
+
+        let ids = |group: &str| -> Vec<u16> {
+            registry
+                .online(group)
+                .iter()
+                .map(|device| device.user_id)
+                .collect()
+        };
+        assert_eq!(ids("lab"), vec![1]);
+        assert_eq!(ids("office"), vec![1, 2]);
+        assert_eq!(registry.list().len(), 3);
+
+        // Connected clients change their groups over the socket.
+        registry.subscribe(&second, groups(&["lab"]));
+        assert_eq!(ids("lab"), vec![1, 2]);
+        assert_eq!(ids("office"), vec![1]);
+    }
+
+    #[test]
+    fn frames_update_the_last_seen_time() {
+        let registry = DeviceRegistry::new();
+        let token = registry.register(1, Vec::new(), HashMap::new());
+        registry.seen(&token);
+        let first_seen = registry.get(1).unwrap().last_seen.unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        registry.seen(&token);
+        assert!(registry.get(1).unwrap().last_seen.unwrap() > first_seen);
+        // Unknown endpoints are ignored.
+        registry.seen("unknown");
+    }
+
+    #[test]
+    fn registering_again_keeps_the_connected_endpoint() {
+        let registry = DeviceRegistry::new();
+        let metadata = HashMap::from([("firmware".to_string(), "1.0".to_string())]);
+        let first = registry.register(1, groups(&["lab"]), metadata);
+        let _first = connect(&registry, &first);
+        let connected_at = registry.get(1).unwrap().connected_at;
+
+        // A second registration while connected: both endpoints are kept.
+        let second = registry.register(1, groups(&["office"]), HashMap::new());
+        assert!(registry.contains(&first) && registry.contains(&second));
+        let device = registry.get(1).unwrap();
+        assert!(device.online);
+        assert_eq!(device.groups, groups(&["office"]));
+        assert_eq!(
+            device.metadata.get("firmware").map(String::as_str),
+            Some("1.0")
+        );
+
+        let _second = connect(&registry, &second);
+        assert_eq!(registry.get(1).unwrap().connected_at, connected_at);
+        assert_eq!(registry.send(1, "hello"), 2);
+
+        // Still online through the second endpoint.
+        registry.disconnected(&first);
+        let device = registry.get(1).unwrap();
+        assert!(device.online && device.disconnected_at.is_none());
+        assert_eq!(registry.send(1, "hello"), 1);
+
+        // Idle endpoints are dropped on the next registration.
+        let third = registry.register(1, groups(&["lab"]), HashMap::new());
+        assert!(!registry.contains(&first));
+        assert!(registry.contains(&second) && registry.contains(&third));
+    }
+
+    #[test]
+    fn devices_are_forgotten_with_their_last_endpoint() {
+        let registry = DeviceRegistry::new();
+        let first = registry.register(1, groups(&["lab"]), HashMap::new());
+        let _first = connect(&registry, &first);
+        let second = registry.register(1, groups(&["lab"]), HashMap::new());
+
+        // The connected endpoint goes, the idle one keeps the device known.
+        assert!(registry.unregister(&first));
+        let device = registry.get(1).unwrap();
+        assert!(!device.online);
+        assert!(registry.online("lab").is_empty());
+
+        assert!(registry.unregister(&second));
+        assert!(registry.get(1).is_none());
+        assert!(registry.list().is_empty());
+        assert!(!registry.unregister(&second));
+        assert!(!registry.set_metadata(1, "firmware", "1.0"));
+    }
+
+    #[test]
+    fn the_command_is_found_after_the_bot_mention() {
+        let mut parser = Parser::new();
+        parser.add_named_pattern(COMMAND, pattern(), PRIORITY, list_devices);
+
+        let command = parser
+            .parse(
+                "Futbolito Bot devices lab".to_string(),
+                Some("Futbolito Bot"),
+            )
+            .unwrap();
+        assert_eq!(command.command, COMMAND);
+        assert_eq!(
+            command.required_arguments,
+            vec![("group".to_string(), "lab".to_string())]
+        );
+        assert!(parser.parse("/devices".to_string(), None).is_ok());
+        assert!(parser
+            .parse("Bot list the devices".to_string(), Some("Bot"))
+            .is_err());
+    }
+}
@@ -0,0 +1,527 @@
+// std.
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+// Tokio.
+use rocket::tokio;
+use rocket::tokio::sync::{mpsc, Notify};
+
+// Future.
+use futures_util::FutureExt;
+
+// Regex.
+use regex::Regex;
+
+// logging.
+use log::{debug, error, info, warn};
+
+// Own.
+use crate::events::Event;
+use crate::ids::RestId;
+use crate::metrics::Metrics;
+use crate::middleware::{Handler, Next, Outcome, Request};
+use crate::shutdown::InFlight;
+use crate::types::{ActivityType, Message};
+use crate::{WebexBotState, WebexClient};
+
+// ###################################################################
+// Worker pool configuration.
+// ###################################################################
+
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerConfig {
+    pub workers: usize,  // Number of concurrent workers.
+    pub capacity: usize, // Events each worker can queue before refusing more.
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            workers: 8,
+            capacity: 64,
+        }
+    }
+}
+
+// ###################################################################
+// Event waiting for a worker.
+// ###################################################################
+
+struct QueuedEvent {
+    event: Event,
+    _in_flight: InFlight, // Keeps the shutdown waiting until processed.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    Closing, // The server is shutting down.
+    Busy,    // The worker of the room is saturated.
+    Closed,  // The workers are gone.
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::Closing => write!(f, "The server is shutting down"),
+            DispatchError::Busy => write!(f, "The worker queue of the room is full"),
+            DispatchError::Closed => write!(f, "The workers are not running"),
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
+
+// ###################################################################
+// Entry point of the events, whatever their transport. Deduplicates
+// them and hands them over to the worker pool.
+// ###################################################################
+
+#[derive(Clone)]
+pub struct Dispatcher {
+    state: WebexBotState,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(state: WebexBotState) -> Self {
+        Dispatcher { state }
+    }
+
+    pub(crate) fn state(&self) -> &WebexBotState {
+        &self.state
+    }
+
+    pub fn client(&self) -> &WebexClient {
+        &self.state.client
+    }
+
+    // ------------------------------------------------------------------------------
+    // Queue an event without waiting for its handler. `key` identifies the
+    // delivery, so a second delivery of the same event is dropped. Event ids must
+    // be REST ids. The events of the bot itself are dropped.
+    // ------------------------------------------------------------------------------
+
+    pub async fn dispatch(&self, key: &str, event: Event) -> Result<(), DispatchError> {
+        let state = &self.state;
+
+        // Refuse new events once the server is shutting down.
+        let in_flight = state
+            .client
+            .shutdown
+            .track()
+            .ok_or(DispatchError::Closing)?;
+
+        // Webex also notifies the messages the bot posts, answering them could loop.
+        if self.is_own(&event).await {
+            debug!("Dropping the event {} of the bot itself", event.id);
+            return Ok(());
+        }
+
+        if !state.dedup.first_delivery(&*state.client.store, key).await {
+            debug!("Dropping the redelivered event {}", key);
+            return Ok(());
+        }
+
+        let person_id = event.actor.as_ref().map(|actor| actor.id.as_str());
+        if let (Some(room_id), Some(person_id)) = (event.room_id.as_deref(), person_id) {
+            if state.client.sessions.is_waiting(room_id, person_id) {
+                process_now(state, event, in_flight);
+                return Ok(());
+            }
+        }
+
+        let queued = state.workers.enqueue(event, in_flight);
+        if queued.is_err() {
+            state.dedup.forget(&*state.client.store, key).await;
+        }
+        queued
+    }
+
+    // Whether the bot is the author of the event. Its id is retrieved once.
+    async fn is_own(&self, event: &Event) -> bool {
+        let Some(actor) = event.actor.as_ref() else {
+            return false;
+        };
+        let me = self
+            .state
+            .me
+            .get_or_try_init(|| async {
+                let me = self.state.client.get_my_details().await?;
+                Ok::<String, reqwest::Error>(uuid_of(&me.id))
+            })
+            .await;
+        match me {
+            Ok(me) => uuid_of(&actor.id) == *me,
+            Err(e) => {
+                warn!("Could not retrieve the bot details: {}", e);
+                false
+            }
+        }
+    }
+}
+
+// Ids arrive as REST ids from the webhooks and as uuids from Mercury.
+fn uuid_of(id: &str) -> String {
+    RestId::decode(id)
+        .map(|id| id.uuid)
+        .unwrap_or_else(|_| id.to_string())
+}
+
+// ###################################################################
+// Bounded worker pool. Events of the same room always go to the same
+// worker, so they are handled in the order they were received, except
+// around a handler waiting for a reply.
+// ###################################################################
+
+#[derive(Clone, Default)]
+pub(crate) struct WorkerPool {
+    senders: Vec<mpsc::Sender<QueuedEvent>>,
+}
+
+impl WorkerPool {
+    // ------------------------------------------------------------------------------
+    // Spawn the workers, each one processing its own queue.
+    // ------------------------------------------------------------------------------
+
+    pub fn start(config: WorkerConfig, state: &mut WebexBotState) {
+        let mut receivers = Vec::new();
+        let mut senders = Vec::new();
+        for _ in 0..config.workers.max(1) {
+            let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+            senders.push(sender);
+            receivers.push(receiver);
+        }
+        state.workers = WorkerPool { senders };
+
+        for (index, receiver) in receivers.into_iter().enumerate() {
+            tokio::spawn(work(index, receiver, state.clone()));
+        }
+        info!("Started {} event workers", config.workers.max(1));
+    }
+
+    // ------------------------------------------------------------------------------
+    // Queue an event without waiting. Fails when the queue of its room is full.
+    // ------------------------------------------------------------------------------
+
+    fn enqueue(&self, event: Event, in_flight: InFlight) -> Result<(), DispatchError> {
+        if self.senders.is_empty() {
+            return Err(DispatchError::Closed);
+        }
+
+        let mut hasher = DefaultHasher::new();
+        event.room_id.hash(&mut hasher);
+        let index = (hasher.finish() % self.senders.len() as u64) as usize;
+
+        self.senders[index]
+            .try_send(QueuedEvent {
+                event,
+                _in_flight: in_flight,
+            })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => DispatchError::Busy,
+                mpsc::error::TrySendError::Closed(_) => DispatchError::Closed,
+            })
+    }
+}
+
+// ------------------------------------------------------------------------------
+// Worker loop. Every event is handled on its own task, which the worker waits for
+// unless the handler releases it to wait for a reply: a conversation can last
+// minutes and would stall the other rooms of the worker. A panic escaping the
+// handlers is logged to the dead letter log and does not take the worker down.
+// ------------------------------------------------------------------------------
+
+async fn work(index: usize, mut receiver: mpsc::Receiver<QueuedEvent>, state: WebexBotState) {
+    while let Some(queued) = receiver.recv().await {
+        let release = Arc::new(Notify::new());
+        let handler =
+            tokio::spawn(RELEASE.scope(release.clone(), run_handler(index, queued, state.clone())));
+        tokio::select! {
+            _ = handler => (),
+            _ = release.notified() => debug!("Worker {} released by a waiting handler", index),
+        }
+    }
+}
+
+async fn run_handler(index: usize, queued: QueuedEvent, state: WebexBotState) {
+    let result = AssertUnwindSafe(async {
+        if !screened(&state, &queued.event).await {
+            process(&state, &queued.event).await;
+        }
+    })
+    .catch_unwind()
+    .await;
+
+    if let Err(panic) = result {
+        dead_letter(index, &queued.event, panic_message(&panic));
+    }
+}
+
+// ------------------------------------------------------------------------------
+// Let the middlewares short-circuit a queued event before its message is
+// retrieved, e.g. to throttle it without spending an API call. Answers to an
+// ongoing conversation aren't screened.
+// ------------------------------------------------------------------------------
+
+async fn screened(state: &WebexBotState, event: &Event) -> bool {
+    let middlewares = state.middlewares.lock().await.clone();
+    let Some(outcome) = middlewares
+        .iter()
+        .find_map(|middleware| middleware.screen(event))
+    else {
+        return false;
+    };
+
+    debug!("The event {} was screened out: {:?}", event.id, outcome);
+    if let Outcome::Reply(reply) = outcome {
+        state.client.send_message(&reply).await;
+    }
+    true
+}
+
+tokio::task_local! {
+    // Set while a worker waits for the handler running on the task.
+    static RELEASE: Arc<Notify>;
+}
+
+// ------------------------------------------------------------------------------
+// Let the worker waiting for the current handler move on to its next event. Does
+// nothing outside of the workers.
+// ------------------------------------------------------------------------------
+
+pub(crate) fn release_worker() {
+    let _ = RELEASE.try_with(|release| release.notify_one());
+}
+
+pub(crate) fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+fn dead_letter(worker: usize, event: &Event, reason: String) {
+    let actor = event.actor.as_ref();
+    error!(
+        target: "rusty_webex::dead_letter",
+        "worker={} event={:?} id={} room={} person={} reason={}",
+        worker,
+        event.activity_type,
+        event.id,
+        event.room_id.as_deref().unwrap_or_default(),
+        actor
+            .and_then(|actor| actor.email_address.as_deref())
+            .or(actor.map(|actor| actor.id.as_str()))
+            .unwrap_or_default(),
+        reason
+    );
+}
+
+// ###################################################################
+// Handle a single event.
+// ###################################################################
+
+pub(crate) async fn process(state: &WebexBotState, event: &Event) {
+    match &event.activity_type {
+        ActivityType::AdaptiveCardSubmit => process_card_action(state, event).await,
+        _ if event.is_new_message() => process_message(state, event).await,
+        other => debug!("Ignoring the {:?} event {}", other, event.id),
+    }
+}
+
+async fn process_message(state: &WebexBotState, event: &Event) {
+    // Retrieve message details as this contains the text for the bot call.
+    let detailed_message_info = state.client.get_message_details(&event.id).await;
+
+    // Log the detailed message contents.
+    log::info!("[Message info]: {:?}\n", &detailed_message_info);
+
+    // Messages answering an ongoing conversation don't go through the parser.
+    let detailed_message_info = match state.client.sessions.deliver(detailed_message_info) {
+        Some(message) => message,
+        None => return,
+    };
+
+    // Parse the actual plain text data/message.
+    let raw_message = detailed_message_info.text.clone().unwrap_or_default();
+    let mention = leading_mention(&detailed_message_info);
+    let (callback, request) = {
+        let parser_unlock = state.parser.lock().await;
+        match parser_unlock.parse(raw_message, mention.as_deref()) {
+            Ok(v) => (
+                *v.callback,
+                Request {
+                    client: state.client.clone(),
+                    message: detailed_message_info,
+                    command: v.command,
+                    required_arguments: v.required_arguments,
+                    optional_arguments: v.optional_arguments,
+                },
+            ),
+            Err(e) => {
+                if let Some(reply) = state.responder.parse_failed(&detailed_message_info, &e) {
+                    state.client.send_message(&reply).await;
+                }
+                return;
+            }
+        }
+    };
+
+    // Run the command through the middleware chain, ending on the callback.
+    debug!("Dispatching message to: {}", request.command);
+    let command = request.command.clone();
+    let message = request.message.clone();
+    let middlewares = state.middlewares.lock().await.clone();
+    let start = Instant::now();
+    let outcome = Next::new(&middlewares, Handler::Command(callback))
+        .run(request)
+        .await;
+    Metrics::get_instance().record_command(&command, &outcome, start.elapsed());
+
+    // Post the reply of a middleware that short-circuited the chain, or let the
+    // user know the handler failed.
+    let reply = match outcome {
+        Outcome::Reply(reply) => Some(reply),
+        Outcome::Failed(e) => state.responder.handler_failed(&message, &command, &e),
+        Outcome::Handled | Outcome::Dropped => None,
+    };
+    if let Some(reply) = reply {
+        state.client.send_message(&reply).await;
+    }
+}
+
+// ------------------------------------------------------------------------------
+// Text of the person mention opening the message, as it appears in the plain
+// text. In group spaces it is the mention of the bot.
+// ------------------------------------------------------------------------------
+
+pub(crate) fn leading_mention(message: &Message) -> Option<String> {
+    static MENTION: OnceLock<Regex> = OnceLock::new();
+    let mention = MENTION.get_or_init(|| {
+        Regex::new(
+            r#"^\s*(?:<p>)?\s*<spark-mention[^>]*data-object-type="person"[^>]*>([^<]*)</spark-mention>"#,
+        )
+        .unwrap()
+    });
+    mention
+        .captures(message.html.as_deref()?)
+        .map(|captures| captures[1].trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+// ------------------------------------------------------------------------------
+// Adaptive card submissions go to the card action handler, if any, through the
+// middleware chain like the commands. Middlewares see them as the "card_action"
+// command, with the submitted inputs as required arguments.
+// ------------------------------------------------------------------------------
+
+async fn process_card_action(state: &WebexBotState, event: &Event) {
+    const COMMAND: &str = "card_action";
+
+    let Some(callback) = state.card_action else {
+        debug!("No card action handler for {}", event.id);
+        return;
+    };
+
+    let action = match state.client.get_attachment_action(&event.id).await {
+        Ok(action) => action,
+        Err(e) => {
+            error!("Could not retrieve the card action {}: {}", event.id, e);
+            return;
+        }
+    };
+
+    // Originating room and person, for the policies and the error reply.
+    let message = Message {
+        room_id: action.room_id.clone(),
+        person_id: action.person_id.clone(),
+        person_email: event
+            .actor
+            .as_ref()
+            .and_then(|actor| actor.email_address.clone()),
+        ..Default::default()
+    };
+    let inputs = action
+        .inputs
+        .iter()
+        .flatten()
+        .map(|(name, value)| match value {
+            serde_json::Value::String(text) => (name.clone(), text.clone()),
+            other => (name.clone(), other.to_string()),
+        })
+        .collect();
+    let request = Request {
+        client: state.client.clone(),
+        message: message.clone(),
+        command: COMMAND.to_string(),
+        required_arguments: inputs,
+        optional_arguments: Vec::new(),
+    };
+
+    let middlewares = state.middlewares.lock().await.clone();
+    let start = Instant::now();
+    let outcome = Next::new(&middlewares, Handler::CardAction(callback, action))
+        .run(request)
+        .await;
+    Metrics::get_instance().record_command(COMMAND, &outcome, start.elapsed());
+
+    let reply = match outcome {
+        Outcome::Reply(reply) => Some(reply),
+        Outcome::Failed(e) => state.responder.handler_failed(&message, COMMAND, &e),
+        Outcome::Handled | Outcome::Dropped => None,
+    };
+    if let Some(reply) = reply {
+        state.client.send_message(&reply).await;
+    }
+}
+
+// ------------------------------------------------------------------------------
+// Answers to an ongoing conversation skip the queue: the handler waiting for
+// them may be the one holding the worker of the room.
+// ------------------------------------------------------------------------------
+
+fn process_now(state: &WebexBotState, event: Event, in_flight: InFlight) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let result = AssertUnwindSafe(process(&state, &event))
+            .catch_unwind()
+            .await;
+        if let Err(panic) = result {
+            warn!("A conversation answer panicked.");
+            dead_letter(usize::MAX, &event, panic_message(&panic));
+        }
+        drop(in_flight);
+    });
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::Sessions;
+    use std::time::Duration;
+
+    #[rocket::async_test]
+    async fn waiting_for_a_reply_releases_the_worker() {
+        let sessions = Sessions::new();
+        let release = Arc::new(Notify::new());
+        let handler = tokio::spawn(RELEASE.scope(release.clone(), async move {
+            sessions
+                .wait_for("room", "person", Duration::from_secs(60))
+                .await
+        }));
+
+        tokio::time::timeout(Duration::from_secs(1), release.notified())
+            .await
+            .expect("the worker was not released");
+        assert!(!handler.is_finished());
+        handler.abort();
+    }
+}
@@ -0,0 +1,288 @@
+// std.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// serde.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Tokio.
+use rocket::tokio;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+// uuid.
+use uuid::Uuid;
+
+// ###################################################################
+// Errors raised while talking to a device.
+// ###################################################################
+
+#[derive(Debug)]
+pub enum ChannelError {
+    Closed,                   // The connection is gone for good.
+    Timeout,                  // The device didn't answer in time.
+    Serde(serde_json::Error), // The envelope couldn't be encoded.
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelError::Closed => write!(f, "The device channel is closed"),
+            ChannelError::Timeout => write!(f, "The device didn't answer in time"),
+            ChannelError::Serde(e) => write!(f, "Invalid envelope: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+impl From<serde_json::Error> for ChannelError {
+    fn from(e: serde_json::Error) -> Self {
+        ChannelError::Serde(e)
+    }
+}
+
+// ###################################################################
+// Typed JSON message exchanged with the devices. Replies carry the id
+// of the request in `correlationId`.
+// ###################################################################
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub id: String, // Unique id of the envelope.
+    #[serde(rename = "type")]
+    pub kind: String, // Application defined type, e.g. "command" or "status".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>, // Id of the request this envelope answers.
+    #[serde(default)]
+    pub payload: Value,
+}
+
+impl Envelope {
+    pub fn new(kind: &str, payload: Value) -> Self {
+        Envelope {
+            id: Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            correlation_id: None,
+            payload,
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Answer to this envelope.
+    // ------------------------------------------------------------------------------
+
+    pub fn reply(&self, kind: &str, payload: Value) -> Self {
+        Envelope {
+            correlation_id: Some(self.id.clone()),
+            ..Envelope::new(kind, payload)
+        }
+    }
+
+    pub fn to_message(&self) -> Result<Message, ChannelError> {
+        Ok(Message::Text(serde_json::to_string(self)?))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Decode a text frame, none when it isn't an envelope.
+    // ------------------------------------------------------------------------------
+
+    pub fn from_message(message: &Message) -> Option<Envelope> {
+        match message {
+            Message::Text(text) => serde_json::from_str(text).ok(),
+            Message::Binary(bytes) => serde_json::from_slice(bytes).ok(),
+            _ => None,
+        }
+    }
+}
+
+// ###################################################################
+// Sending half of a device connection, able to await replies.
+// ###################################################################
+
+type Pending = Arc<Mutex<HashMap<String, oneshot::Sender<Envelope>>>>;
+
+#[derive(Clone)]
+pub struct DeviceChannel {
+    outbound: Sender<Message>,
+    pending: Pending,
+}
+
+impl DeviceChannel {
+    // ------------------------------------------------------------------------------
+    // Route the replies of the requests, every other frame goes to the receiver.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn new(
+        outbound: Sender<Message>,
+        mut inbound: Receiver<Message>,
+    ) -> (DeviceChannel, Receiver<Message>) {
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let (sender, receiver) = mpsc::channel(32);
+
+        let waiting = pending.clone();
+        tokio::spawn(async move {
+            while let Some(message) = inbound.recv().await {
+                let reply = Envelope::from_message(&message).and_then(|envelope| {
+                    let waiter = envelope
+                        .correlation_id
+                        .as_ref()
+                        .and_then(|id| waiting.lock().unwrap().remove(id));
+                    waiter.map(|waiter| (waiter, envelope))
+                });
+
+                match reply {
+                    Some((waiter, envelope)) => {
+                        let _ = waiter.send(envelope);
+                    }
+                    None => {
+                        if sender.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        (DeviceChannel { outbound, pending }, receiver)
+    }
+
+    pub async fn send(&self, message: Message) -> Result<(), ChannelError> {
+        self.outbound
+            .send(message)
+            .await
+            .map_err(|_| ChannelError::Closed)
+    }
+
+    pub async fn send_envelope(&self, envelope: &Envelope) -> Result<(), ChannelError> {
+        self.send(envelope.to_message()?).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // Send the envelope and wait for the envelope answering it.
+    // ------------------------------------------------------------------------------
+
+    pub async fn request(
+        &self,
+        envelope: &Envelope,
+        timeout: Duration,
+    ) -> Result<Envelope, ChannelError> {
+        let (waiter, reply) = oneshot::channel();
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(envelope.id.clone(), waiter);
+
+        let result = match self.send_envelope(envelope).await {
+            Ok(()) => match tokio::time::timeout(timeout, reply).await {
+                Ok(Ok(reply)) => Ok(reply),
+                Ok(Err(_)) => Err(ChannelError::Closed),
+                Err(_) => Err(ChannelError::Timeout),
+            },
+            Err(e) => Err(e),
+        };
+
+        self.pending.lock().unwrap().remove(&envelope.id);
+        result
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Channel wired to an in-memory device: what the bot sends and what the device
+    // pushes back.
+    fn channel() -> (
+        DeviceChannel,
+        Receiver<Message>,
+        Receiver<Message>,
+        Sender<Message>,
+    ) {
+        let (outbound, sent) = mpsc::channel(8);
+        let (device, inbound) = mpsc::channel(8);
+        let (channel, receiver) = DeviceChannel::new(outbound, inbound);
+        (channel, receiver, sent, device)
+    }
+
+    #[rocket::async_test]
+    async fn replies_are_routed_to_their_request() {
+        let (channel, mut receiver, mut sent, device) = channel();
+        tokio::spawn(async move {
+            let message = sent.recv().await.unwrap();
+            let request = Envelope::from_message(&message).unwrap();
+            let reply = request.reply("status", json!({ "led": "on" }));
+            device.send(reply.to_message().unwrap()).await.unwrap();
+            device
+                .send(Message::Text("unrelated".to_string()))
+                .await
+                .unwrap();
+        });
+
+        let request = Envelope::new("status", json!({}));
+        let reply = channel
+            .request(&request, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(reply.correlation_id.as_deref(), Some(request.id.as_str()));
+        assert_eq!(reply.payload, json!({ "led": "on" }));
+        assert!(channel.pending.lock().unwrap().is_empty());
+
+        // Only the reply was taken, the other frame still reaches the receiver.
+        assert_eq!(
+            receiver.recv().await,
+            Some(Message::Text("unrelated".to_string()))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn frames_answering_no_request_reach_the_receiver() {
+        let (_channel, mut receiver, _sent, device) = channel();
+        let event = Envelope::new("goal", json!({ "team": "blue" }));
+        let stray = Envelope::new("status", json!({})).reply("status", json!({}));
+        for frame in [
+            event.to_message().unwrap(),
+            stray.to_message().unwrap(),
+            Message::Binary(b"raw".to_vec()),
+        ] {
+            device.send(frame.clone()).await.unwrap();
+            assert_eq!(receiver.recv().await, Some(frame));
+        }
+    }
+
+    #[rocket::async_test]
+    async fn unanswered_requests_time_out_and_are_forgotten() {
+        let (channel, mut receiver, mut sent, device) = channel();
+        let request = Envelope::new("status", json!({}));
+
+        let result = channel.request(&request, Duration::from_millis(20)).await;
+        assert!(matches!(result, Err(ChannelError::Timeout)));
+        assert!(channel.pending.lock().unwrap().is_empty());
+
+        // A late reply is handed over to the receiver like any other frame.
+        assert!(sent.recv().await.is_some());
+        let late = request.reply("status", json!({})).to_message().unwrap();
+        device.send(late.clone()).await.unwrap();
+        assert_eq!(receiver.recv().await, Some(late));
+    }
+
+    #[rocket::async_test]
+    async fn requests_fail_once_the_connection_is_gone() {
+        let (channel, _receiver, sent, _device) = channel();
+        drop(sent);
+        let request = Envelope::new("status", json!({}));
+        let result = channel.request(&request, Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(ChannelError::Closed)));
+        assert!(channel.pending.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+// std.
+use std::str::FromStr;
+use std::sync::Arc;
+
+// serde.
+use serde_json::Value;
+
+// Rocket.
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{post, routes, tokio, Build, Rocket, State};
+
+// logging.
+use log::{debug, error, info, warn};
+
+// Own.
+use crate::dispatcher::{DispatchError, Dispatcher};
+use crate::events::Event;
+use crate::ids::{IdType, RestId};
+use crate::reconnect::{ConnectionState, ReconnectPolicy, StateCallback};
+use crate::types::{ActivityType, Response};
+use crate::webex_websocket::WebexWebsocketClient;
+
+// ###################################################################
+// Transport delivering the events to the bot.
+// ###################################################################
+
+/**
+ * A source hands every event it receives to the dispatcher, which runs the
+ * same parser, middlewares and card action handler whatever the transport.
+ */
+pub trait EventSource: Send {
+    // Hook the source up to the server at launch: mount routes, spawn tasks...
+    fn attach(self: Box<Self>, server: Rocket<Build>, dispatcher: Dispatcher) -> Rocket<Build>;
+}
+
+// ###################################################################
+// Built-in transports, selectable by name ("webhook" or "websocket").
+// ###################################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    #[default]
+    Webhook, // Webex posts the events to a public URL.
+    Websocket, // The bot pulls the events through Mercury, no public URL needed.
+}
+
+impl Transport {
+    pub fn source(self) -> Box<dyn EventSource> {
+        match self {
+            Transport::Webhook => Box::new(WebhookSource),
+            Transport::Websocket => Box::new(WebsocketSource::default()),
+        }
+    }
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "webhook" | "webhooks" => Ok(Transport::Webhook),
+            "websocket" | "websockets" | "mercury" => Ok(Transport::Websocket),
+            other => Err(format!("Unknown transport: {}", other)),
+        }
+    }
+}
+
+// ###################################################################
+// Webhook transport.
+// ###################################################################
+
+pub struct WebhookSource;
+
+impl EventSource for WebhookSource {
+    fn attach(self: Box<Self>, server: Rocket<Build>, dispatcher: Dispatcher) -> Rocket<Build> {
+        server
+            .mount("/", routes![webhook_listener])
+            .manage(dispatcher)
+    }
+}
+
+// Path of the webhook listener, as in the route below.
+pub const WEBHOOK_PATH: &str = "/cats/futbolito";
+
+#[post("/cats/futbolito", format = "json", data = "<data>")]
+pub(crate) async fn webhook_listener(
+    data: Json<Response<Value>>,
+    dispatcher: &State<Dispatcher>,
+) -> Status {
+    let response = data.into_inner();
+    let key = format!(
+        "{}:{}",
+        response.id,
+        response
+            .data
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    );
+
+    // Acknowledge right away, the handlers run on the worker pool. Webex delivers
+    // the event again later when the server can't take it.
+    match dispatcher.dispatch(&key, Event::from(&response)).await {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            warn!("Could not queue the webhook event: {}", e);
+            Status::ServiceUnavailable
+        }
+    }
+}
+
+// ###################################################################
+// Websocket (Mercury) transport.
+// ###################################################################
+
+pub struct WebsocketSource {
+    device_name: String,
+    cluster: String, // Cluster the REST ids are encoded within.
+    policy: ReconnectPolicy,
+    on_state: Option<StateCallback>,
+}
+
+impl Default for WebsocketSource {
+    fn default() -> Self {
+        WebsocketSource {
+            device_name: "rusty-webex".to_string(),
+            cluster: RestId::DEFAULT_CLUSTER.to_string(),
+            policy: ReconnectPolicy::default(),
+            on_state: None,
+        }
+    }
+}
+
+impl WebsocketSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn device_name(mut self, name: &str) -> Self {
+        self.device_name = name.to_string();
+        self
+    }
+
+    pub fn cluster(mut self, cluster: &str) -> Self {
+        self.cluster = cluster.to_string();
+        self
+    }
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Called on every connection state change, e.g. to alert when the bot is offline.
+    // ------------------------------------------------------------------------------
+
+    pub fn on_state_change<F: Fn(&ConnectionState) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.on_state = Some(Arc::new(callback));
+        self
+    }
+
+    async fn run(self, dispatcher: Dispatcher) {
+        let client = dispatcher.client().clone();
+
+        let mut mercury = WebexWebsocketClient::new(&client.bearer_token)
+            .device_name(&self.device_name)
+            .reconnect(self.policy.clone())
+            .shutdown(client.shutdown.clone());
+        if let Some(on_state) = self.on_state.clone() {
+            mercury = mercury.on_state_change(move |state| on_state(state));
+        }
+        let mut frames = match mercury.run().await {
+            Ok(frames) => frames,
+            Err(e) => {
+                error!("Could not connect to Mercury: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            let frame = tokio::select! {
+                frame = frames.recv() => frame,
+                _ = client.shutdown.closed() => break,
+            };
+            let Some(frame) = frame else {
+                warn!("Gave up reconnecting to Mercury.");
+                break;
+            };
+
+            // The dispatcher drops the messages the bot posts itself.
+            let Some(event) = self.to_rest(Event::from(&frame)) else {
+                debug!("Ignoring the Mercury event {:?}", frame.event_type());
+                continue;
+            };
+
+            let key = format!("mercury:{}", frame.id);
+            match dispatcher.dispatch(&key, event).await {
+                Ok(()) => (),
+                Err(DispatchError::Closing) => break,
+                Err(e) => warn!("Dropping the Mercury event {}: {}", frame.id, e),
+            }
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Keep the events the dispatcher handles, with their ids encoded as REST ids.
+    // ------------------------------------------------------------------------------
+
+    fn to_rest(&self, mut event: Event) -> Option<Event> {
+        let id_type = match event.activity_type {
+            ActivityType::AdaptiveCardSubmit => IdType::AttachmentAction,
+            _ if event.is_new_message() => IdType::Message,
+            _ => return None,
+        };
+
+        event.id = event.rest_id(id_type, &self.cluster);
+        event.room_id = event.rest_room_id(&self.cluster);
+        if let Some(actor) = event
+            .actor
+            .as_mut()
+            .filter(|actor| RestId::decode(&actor.id).is_err())
+        {
+            actor.id = RestId::new(IdType::People, &actor.id)
+                .cluster(&self.cluster)
+                .encode();
+        }
+        Some(event)
+    }
+}
+
+impl EventSource for WebsocketSource {
+    fn attach(self: Box<Self>, server: Rocket<Build>, dispatcher: Dispatcher) -> Rocket<Build> {
+        // No webhook is needed to receive the events.
+        dispatcher.state().readiness.webhooks_optional();
+        info!("Receiving the events through Mercury.");
+        tokio::spawn(self.run(dispatcher));
+        server
+    }
+}
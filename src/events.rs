@@ -0,0 +1,329 @@
+// serde.
+use serde_json::Value;
+
+// Own.
+use crate::ids::{IdType, RestId};
+use crate::types::{ActivityType, Actor, MessageActivity, Response, SpaceActivity};
+use crate::webex_websocket::MercuryMessage;
+
+// ###################################################################
+// Event received by the bot, decoded from a Mercury frame or from a
+// webhook payload.
+// ###################################################################
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub activity_type: ActivityType, // What happened.
+    pub id: String, // Activity (Mercury, raw UUID) or resource (webhook, REST id) identifier.
+    pub room_id: Option<String>, // Space the event happened in, same id format as `id`.
+    pub actor: Option<Actor>, // Who triggered the event.
+    pub created: Option<String>, // When the event happened.
+    pub data: Value, // Raw payload, for the details not decoded here.
+}
+
+impl Event {
+    const ACTIVITY: &'static str = "conversation.activity";
+
+    // ------------------------------------------------------------------------------
+    // Whether the event is a new message, i.e. something the parser should see.
+    // ------------------------------------------------------------------------------
+
+    pub fn is_new_message(&self) -> bool {
+        matches!(&self.activity_type, ActivityType::Message(activity) if activity.is_created())
+    }
+
+    // ------------------------------------------------------------------------------
+    // REST id of the event resource, e.g. for `get_message_details`. Webhook ids
+    // are already REST ids; Mercury UUIDs are encoded within the given cluster.
+    // ------------------------------------------------------------------------------
+
+    pub fn rest_id(&self, id_type: IdType, cluster: &str) -> String {
+        to_rest_id(&self.id, id_type, cluster)
+    }
+
+    pub fn rest_room_id(&self, cluster: &str) -> Option<String> {
+        self.room_id
+            .as_deref()
+            .map(|room_id| to_rest_id(room_id, IdType::Room, cluster))
+    }
+}
+
+fn to_rest_id(id: &str, id_type: IdType, cluster: &str) -> String {
+    match RestId::decode(id) {
+        Ok(_) => id.to_string(),
+        Err(_) => RestId::new(id_type, id).cluster(cluster).encode(),
+    }
+}
+
+// ------------------------------------------------------------------------------
+// Mercury frames. `conversation.activity` events are decoded from their verb,
+// unknown verbs become `Unknown("conversation.activity.<verb>")`.
+// ------------------------------------------------------------------------------
+
+impl From<&MercuryMessage> for Event {
+    fn from(message: &MercuryMessage) -> Event {
+        let event_type = message.event_type().unwrap_or_default();
+        let activity = message.data.get("activity").unwrap_or(&Value::Null);
+        let text =
+            |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(str::to_string);
+
+        let activity_type = match event_type {
+            Event::ACTIVITY => {
+                let verb = activity
+                    .get("verb")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                decode_verb(verb)
+            }
+            "conversation.highlight" => ActivityType::Highlight,
+            "status.start_typing" => ActivityType::StartTyping,
+            _ if event_type.starts_with("locus.") => ActivityType::Locus,
+            _ if event_type.starts_with("janus.") => ActivityType::Janus,
+            _ => ActivityType::Unknown(event_type.to_string()),
+        };
+
+        Event {
+            activity_type,
+            id: text(activity, "id").unwrap_or_default(),
+            room_id: activity.get("target").and_then(|target| text(target, "id")),
+            actor: activity
+                .get("actor")
+                .and_then(|actor| serde_json::from_value(actor.clone()).ok()),
+            created: text(activity, "published"),
+            data: message.data.clone(),
+        }
+    }
+}
+
+fn decode_verb(verb: &str) -> ActivityType {
+    if verb == "cardAction" {
+        return ActivityType::AdaptiveCardSubmit;
+    }
+    if let Ok(activity) = MessageActivity::try_from(verb) {
+        return ActivityType::Message(activity);
+    }
+    if let Ok(activity) = SpaceActivity::try_from(verb) {
+        return ActivityType::Space(activity);
+    }
+    ActivityType::Unknown(format!("{}.{}", Event::ACTIVITY, verb))
+}
+
+// ------------------------------------------------------------------------------
+// Webhook payloads, decoded from their resource and event. Unknown pairs become
+// `Unknown("<resource>.<event>")`.
+// ------------------------------------------------------------------------------
+
+impl From<&Response<Value>> for Event {
+    fn from(response: &Response<Value>) -> Event {
+        let activity_type = match (response.resource.as_str(), response.event.as_str()) {
+            ("messages", "created") => ActivityType::Message(MessageActivity::Posted),
+            ("messages", "deleted") => ActivityType::Message(MessageActivity::Deleted),
+            ("attachmentActions", "created") => ActivityType::AdaptiveCardSubmit,
+            ("memberships", "created") => ActivityType::Space(SpaceActivity::Joined),
+            ("memberships", "deleted") => ActivityType::Space(SpaceActivity::Left),
+            ("memberships", "updated") | ("rooms", "updated") => {
+                ActivityType::Space(SpaceActivity::Changed)
+            }
+            ("rooms", "created") => ActivityType::Space(SpaceActivity::Created),
+            (resource, event) => ActivityType::Unknown(format!("{}.{}", resource, event)),
+        };
+
+        let text = |key: &str| {
+            response
+                .data
+                .get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        Event {
+            activity_type,
+            id: text("id").unwrap_or_default(),
+            room_id: text("roomId"),
+            actor: Some(Actor {
+                id: response.actor_id.clone(),
+                email_address: text("personEmail"),
+                ..Default::default()
+            }),
+            created: Some(response.created.clone()),
+            data: response.data.clone(),
+        }
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mercury(event_type: &str, verb: &str) -> MercuryMessage {
+        MercuryMessage {
+            data: json!({
+                "eventType": event_type,
+                "activity": {
+                    "id": "activity-uuid",
+                    "verb": verb,
+                    "published": "2024-01-01T00:00:00.000Z",
+                    "target": { "id": "room-uuid" },
+                    "actor": {
+                        "id": "person-uuid",
+                        "objectType": "person",
+                        "emailAddress": "someone@example.com",
+                        "entryUUID": "person-uuid"
+                    }
+                }
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn webhook(resource: &str, event: &str) -> Response<Value> {
+        Response {
+            id: "webhook".to_string(),
+            name: "bot".to_string(),
+            target_url: "https://bot.example.com/".to_string(),
+            resource: resource.to_string(),
+            event: event.to_string(),
+            created: "2024-01-01T00:00:00.000Z".to_string(),
+            actor_id: "person".to_string(),
+            data: json!({
+                "id": "resource",
+                "roomId": "room",
+                "personEmail": "someone@example.com"
+            }),
+        }
+    }
+
+    #[test]
+    fn mercury_activities_are_decoded_from_their_verb() {
+        let cases = [
+            ("post", ActivityType::Message(MessageActivity::Posted)),
+            ("share", ActivityType::Message(MessageActivity::Shared)),
+            ("delete", ActivityType::Message(MessageActivity::Deleted)),
+            ("cardAction", ActivityType::AdaptiveCardSubmit),
+            ("add", ActivityType::Space(SpaceActivity::Joined)),
+            ("leave", ActivityType::Space(SpaceActivity::Left)),
+            (
+                "tag",
+                ActivityType::Unknown("conversation.activity.tag".to_string()),
+            ),
+        ];
+        for (verb, expected) in cases {
+            let event = Event::from(&mercury("conversation.activity", verb));
+            assert_eq!(event.activity_type, expected, "verb {}", verb);
+        }
+    }
+
+    #[test]
+    fn mercury_event_types_are_decoded() {
+        let cases = [
+            ("conversation.highlight", ActivityType::Highlight),
+            ("status.start_typing", ActivityType::StartTyping),
+            ("locus.difference", ActivityType::Locus),
+            ("janus.user_sessions", ActivityType::Janus),
+            (
+                "apheleia.subscription_update",
+                ActivityType::Unknown("apheleia.subscription_update".to_string()),
+            ),
+            ("", ActivityType::Unknown(String::new())),
+        ];
+        for (event_type, expected) in cases {
+            let event = Event::from(&mercury(event_type, "post"));
+            assert_eq!(event.activity_type, expected, "event type {}", event_type);
+        }
+    }
+
+    #[test]
+    fn mercury_activity_details_are_kept() {
+        let event = Event::from(&mercury("conversation.activity", "post"));
+        assert!(event.is_new_message());
+        assert_eq!(event.id, "activity-uuid");
+        assert_eq!(event.room_id.as_deref(), Some("room-uuid"));
+        assert_eq!(event.created.as_deref(), Some("2024-01-01T00:00:00.000Z"));
+        let actor = event.actor.unwrap();
+        assert_eq!(actor.id, "person-uuid");
+        assert_eq!(actor.email_address.as_deref(), Some("someone@example.com"));
+
+        let frame = MercuryMessage::default();
+        let event = Event::from(&frame);
+        assert_eq!(event.activity_type, ActivityType::Unknown(String::new()));
+        assert!(event.id.is_empty() && event.room_id.is_none() && event.actor.is_none());
+    }
+
+    #[test]
+    fn webhooks_are_decoded_from_their_resource_and_event() {
+        let cases = [
+            (
+                "messages",
+                "created",
+                ActivityType::Message(MessageActivity::Posted),
+            ),
+            (
+                "messages",
+                "deleted",
+                ActivityType::Message(MessageActivity::Deleted),
+            ),
+            (
+                "attachmentActions",
+                "created",
+                ActivityType::AdaptiveCardSubmit,
+            ),
+            (
+                "memberships",
+                "created",
+                ActivityType::Space(SpaceActivity::Joined),
+            ),
+            (
+                "memberships",
+                "deleted",
+                ActivityType::Space(SpaceActivity::Left),
+            ),
+            (
+                "memberships",
+                "updated",
+                ActivityType::Space(SpaceActivity::Changed),
+            ),
+            (
+                "rooms",
+                "updated",
+                ActivityType::Space(SpaceActivity::Changed),
+            ),
+            (
+                "rooms",
+                "created",
+                ActivityType::Space(SpaceActivity::Created),
+            ),
+            (
+                "messages",
+                "updated",
+                ActivityType::Unknown("messages.updated".to_string()),
+            ),
+            (
+                "meetings",
+                "started",
+                ActivityType::Unknown("meetings.started".to_string()),
+            ),
+        ];
+        for (resource, event, expected) in cases {
+            let decoded = Event::from(&webhook(resource, event));
+            assert_eq!(decoded.activity_type, expected, "{}.{}", resource, event);
+        }
+    }
+
+    #[test]
+    fn webhook_details_are_kept() {
+        let event = Event::from(&webhook("messages", "created"));
+        assert!(event.is_new_message());
+        assert_eq!(event.id, "resource");
+        assert_eq!(event.room_id.as_deref(), Some("room"));
+        assert_eq!(event.created.as_deref(), Some("2024-01-01T00:00:00.000Z"));
+        let actor = event.actor.unwrap();
+        assert_eq!(actor.id, "person");
+        assert_eq!(actor.email_address.as_deref(), Some("someone@example.com"));
+
+        assert!(!Event::from(&webhook("messages", "deleted")).is_new_message());
+    }
+}
@@ -0,0 +1,185 @@
+// std.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Rocket.
+use rocket::http::{ContentType, Status};
+use rocket::tokio;
+use rocket::{get, State};
+
+// logging.
+use log::{debug, warn};
+
+// Own.
+use crate::event_source::WEBHOOK_PATH;
+use crate::metrics::Metrics;
+use crate::types::Webhook;
+use crate::{WebexBotState, WebexClient};
+
+// ###################################################################
+// Readiness of the bot: the token works and webhooks deliver events.
+// ###################################################################
+
+#[derive(Default)]
+pub(crate) struct Readiness {
+    token_valid: AtomicBool,
+    webhooks_registered: AtomicBool,
+    webhooks_optional: AtomicBool, // Events arrive through another transport.
+}
+
+impl Readiness {
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn is_ready(&self) -> bool {
+        self.token_valid.load(Ordering::Relaxed)
+            && (self.webhooks_optional.load(Ordering::Relaxed)
+                || self.webhooks_registered.load(Ordering::Relaxed))
+    }
+
+    pub fn webhooks_optional(&self) {
+        self.webhooks_optional.store(true, Ordering::Relaxed);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Validate the token and look for active webhooks delivering to this server,
+    // periodically.
+    // ------------------------------------------------------------------------------
+
+    pub async fn watch(&self, client: WebexClient, webhook_url: Option<String>) {
+        loop {
+            let token_valid = match client.get_my_details().await {
+                Ok(me) => {
+                    debug!("Token validated for {}", me.display_name);
+                    true
+                }
+                Err(e) => {
+                    warn!("Could not validate the token: {}", e);
+                    false
+                }
+            };
+
+            let webhooks_registered = match client.list_webhooks().await {
+                Ok(webhooks) => webhooks.iter().any(|webhook| {
+                    webhook.status == "active" && delivers_here(webhook, webhook_url.as_deref())
+                }),
+                Err(e) => {
+                    warn!("Could not list the webhooks: {}", e);
+                    false
+                }
+            };
+            if token_valid
+                && !webhooks_registered
+                && !self.webhooks_optional.load(Ordering::Relaxed)
+            {
+                warn!("No active webhook delivers the events to this server.");
+            }
+
+            self.token_valid.store(token_valid, Ordering::Relaxed);
+            self.webhooks_registered
+                .store(webhooks_registered, Ordering::Relaxed);
+            tokio::time::sleep(Self::INTERVAL).await;
+        }
+    }
+}
+
+// Whether the webhook posts to the configured url, or to the listener path when
+// the public url of the server is not known.
+fn delivers_here(webhook: &Webhook, webhook_url: Option<&str>) -> bool {
+    let target = webhook.target_url.trim_end_matches('/');
+    match webhook_url {
+        Some(url) => target == url.trim_end_matches('/'),
+        None => target.ends_with(WEBHOOK_PATH),
+    }
+}
+
+// #########################################################################################
+// Liveness probe.
+// #########################################################################################
+
+#[get("/healthz")]
+pub(crate) fn healthz() -> &'static str {
+    "OK"
+}
+
+// #########################################################################################
+// Readiness probe.
+// #########################################################################################
+
+#[get("/readyz")]
+pub(crate) fn readyz(state: &State<WebexBotState>) -> (Status, &'static str) {
+    if state.readiness.is_ready() && !state.client.shutdown.is_closing() {
+        (Status::Ok, "READY")
+    } else {
+        (Status::ServiceUnavailable, "NOT READY")
+    }
+}
+
+// #########################################################################################
+// Prometheus metrics.
+// #########################################################################################
+
+#[get("/metrics")]
+pub(crate) fn metrics() -> (ContentType, String) {
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        Metrics::get_instance().render(),
+    )
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(target_url: &str) -> Webhook {
+        Webhook {
+            target_url: target_url.to_string(),
+            status: "active".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn webhooks_must_target_the_configured_url() {
+        let url = Some("https://bot.example.com/cats/futbolito");
+        assert!(delivers_here(
+            &webhook("https://bot.example.com/cats/futbolito"),
+            url
+        ));
+        assert!(delivers_here(
+            &webhook("https://bot.example.com/cats/futbolito/"),
+            url
+        ));
+        assert!(delivers_here(
+            &webhook("https://bot.example.com/cats/futbolito"),
+            Some("https://bot.example.com/cats/futbolito/")
+        ));
+        assert!(!delivers_here(
+            &webhook("https://old.example.com/cats/futbolito"),
+            url
+        ));
+        assert!(!delivers_here(
+            &webhook("https://bot.example.com/other"),
+            url
+        ));
+    }
+
+    #[test]
+    fn without_a_url_webhooks_must_target_the_listener_path() {
+        assert!(delivers_here(
+            &webhook("https://anywhere.example.com/cats/futbolito"),
+            None
+        ));
+        assert!(delivers_here(
+            &webhook("http://10.0.0.1:8000/cats/futbolito/"),
+            None
+        ));
+        assert!(!delivers_here(
+            &webhook("https://anywhere.example.com/hooks"),
+            None
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+// std.
+use std::fmt;
+use std::str::FromStr;
+
+// base64.
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE_NO_PAD};
+use base64::Engine;
+
+// ###################################################################
+// Errors raised while decoding REST ids.
+// ###################################################################
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdError {
+    Base64(String),      // Not base64 encoded.
+    Format(String),      // Not a `ciscospark://<cluster>/<TYPE>/<uuid>` URI.
+    UnknownType(String), // Resource type this module doesn't know.
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdError::Base64(e) => write!(f, "Invalid base64 id: {}", e),
+            IdError::Format(id) => write!(f, "Invalid Webex id: {}", id),
+            IdError::UnknownType(kind) => write!(f, "Unknown Webex id type: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for IdError {}
+
+// ###################################################################
+// Resource types found in REST ids.
+// ###################################################################
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdType {
+    Message,
+    Room,
+    People,
+    Team,
+    AttachmentAction,
+}
+
+impl IdType {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            IdType::Message => "MESSAGE",
+            IdType::Room => "ROOM",
+            IdType::People => "PEOPLE",
+            IdType::Team => "TEAM",
+            IdType::AttachmentAction => "ATTACHMENT_ACTION",
+        }
+    }
+}
+
+impl FromStr for IdType {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, IdError> {
+        match s {
+            "MESSAGE" => Ok(IdType::Message),
+            "ROOM" => Ok(IdType::Room),
+            "PEOPLE" => Ok(IdType::People),
+            "TEAM" => Ok(IdType::Team),
+            "ATTACHMENT_ACTION" => Ok(IdType::AttachmentAction),
+            _ => Err(IdError::UnknownType(s.to_string())),
+        }
+    }
+}
+
+// ###################################################################
+// REST id: base64 of `ciscospark://<cluster>/<TYPE>/<uuid>`. Mercury
+// only carries the raw UUID.
+// ###################################################################
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RestId {
+    pub id_type: IdType,
+    pub cluster: String, // "us", or e.g. "urn:TEAM:eu-central-1_k" for other regions.
+    pub uuid: String,
+}
+
+impl RestId {
+    const SCHEME: &'static str = "ciscospark://";
+    pub const DEFAULT_CLUSTER: &'static str = "us";
+
+    pub fn new(id_type: IdType, uuid: &str) -> Self {
+        RestId {
+            id_type,
+            cluster: Self::DEFAULT_CLUSTER.to_string(),
+            uuid: uuid.to_string(),
+        }
+    }
+
+    pub fn cluster(mut self, cluster: &str) -> Self {
+        self.cluster = cluster.to_string();
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Base64 id as used by the REST API, without padding like Webex does.
+    // ------------------------------------------------------------------------------
+
+    pub fn encode(&self) -> String {
+        STANDARD_NO_PAD.encode(format!(
+            "{}{}/{}/{}",
+            Self::SCHEME,
+            self.cluster,
+            self.id_type.as_str(),
+            self.uuid
+        ))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Accepts padded, unpadded and URL safe encodings.
+    // ------------------------------------------------------------------------------
+
+    pub fn decode(id: &str) -> Result<RestId, IdError> {
+        let trimmed = id.trim().trim_end_matches('=');
+        let bytes = STANDARD_NO_PAD
+            .decode(trimmed)
+            .or_else(|_| URL_SAFE_NO_PAD.decode(trimmed))
+            .map_err(|e| IdError::Base64(e.to_string()))?;
+        let uri = String::from_utf8(bytes).map_err(|_| IdError::Format(id.to_string()))?;
+
+        // Clusters like "urn:TEAM:eu-central-1_k" have colons, split from the end.
+        let path = uri
+            .strip_prefix(Self::SCHEME)
+            .ok_or_else(|| IdError::Format(uri.clone()))?;
+        let mut segments = path.rsplitn(3, '/');
+        let (uuid, id_type, cluster) = match (segments.next(), segments.next(), segments.next()) {
+            (Some(uuid), Some(id_type), Some(cluster))
+                if !uuid.is_empty() && !cluster.is_empty() =>
+            {
+                (uuid, id_type, cluster)
+            }
+            _ => return Err(IdError::Format(uri.clone())),
+        };
+
+        Ok(RestId {
+            id_type: id_type.parse()?,
+            cluster: cluster.to_string(),
+            uuid: uuid.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+impl FromStr for RestId {
+    type Err = IdError;
+
+    fn from_str(s: &str) -> Result<Self, IdError> {
+        RestId::decode(s)
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "6d2a7e40-9d4a-11ee-b0d3-0b5d5b6f8c11";
+
+    #[test]
+    fn ids_round_trip_for_every_type() {
+        for id_type in [
+            IdType::Message,
+            IdType::Room,
+            IdType::People,
+            IdType::Team,
+            IdType::AttachmentAction,
+        ] {
+            let id = RestId::new(id_type, UUID);
+            assert_eq!(RestId::decode(&id.encode()), Ok(id));
+        }
+    }
+
+    #[test]
+    fn urn_clusters_round_trip() {
+        let id = RestId::new(IdType::Message, UUID).cluster("urn:TEAM:eu-central-1_k");
+        let encoded = id.encode();
+        assert_eq!(
+            STANDARD_NO_PAD.decode(&encoded).unwrap(),
+            format!("ciscospark://urn:TEAM:eu-central-1_k/MESSAGE/{}", UUID).into_bytes()
+        );
+        assert_eq!(encoded.parse::<RestId>(), Ok(id));
+    }
+
+    #[test]
+    fn padded_and_url_safe_encodings_are_accepted() {
+        let id = RestId::new(IdType::Room, UUID);
+        let uri = format!("ciscospark://us/ROOM/{}", UUID);
+
+        let padded = base64::engine::general_purpose::STANDARD.encode(&uri);
+        assert_eq!(RestId::decode(&padded), Ok(id.clone()));
+        assert_eq!(RestId::decode(&URL_SAFE_NO_PAD.encode(&uri)), Ok(id));
+    }
+
+    #[test]
+    fn malformed_ids_are_refused() {
+        assert!(matches!(
+            RestId::decode("not base64!"),
+            Err(IdError::Base64(_))
+        ));
+        let unknown = STANDARD_NO_PAD.encode(format!("ciscospark://us/WIDGET/{}", UUID));
+        assert_eq!(
+            RestId::decode(&unknown),
+            Err(IdError::UnknownType("WIDGET".to_string()))
+        );
+        let short = STANDARD_NO_PAD.encode("ciscospark://us/MESSAGE");
+        assert!(matches!(RestId::decode(&short), Err(IdError::Format(_))));
+    }
+}
@@ -1,6 +1,8 @@
 // std.
+use std::collections::HashMap;
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 
 // http.
 use http::HeaderMap;
@@ -16,29 +18,68 @@ use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::Mutex;
-use tokio_tungstenite::WebSocketStream;
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::{connect_async_tls_with_config, tungstenite::protocol::Message, Connector};
 
 // Rocket.
-use rocket::serde::json::Json;
-use rocket::{fs::FileServer, get, post, routes, Build, Rocket, State};
+use rocket::{fs::FileServer, get, routes, Build, Rocket};
 use rocket::{tokio, Error as RocketError, Ignite};
 
 // logging.
-use log::{debug, error, info};
-
-// Future
-use futures_util::stream::{SplitSink, SplitStream};
-use futures_util::{SinkExt, StreamExt};
+use log::{debug, info};
 
 // Own modules, crates and type imports.
-use crate::types::{MessageEventResponse, Publish, Register, RegisterResponse, Response};
+use crate::types::{Publish, Register, RegisterResponse};
+use dedup::Deduplicator;
+use device_auth::BrokerAuth;
+use dispatcher::{Dispatcher, WorkerConfig, WorkerPool};
+use envelope::DeviceChannel;
+use event_source::{EventSource, Transport};
+use health::Readiness;
+use middleware::Middleware;
 use parser::Parser;
-use types::{Argument, Callback, Message as OwnMessage, MessageOut};
+use reconnect::{supervise, Connect, ConnectionState, ReconnectPolicy, StateCallback, WsStream};
+pub use regex::Regex;
+use responder::ErrorResponder;
+use scheduler::{Job, Scheduler, SchedulerError, Trigger};
+use sessions::{SessionError, Sessions};
+use shutdown::{Shutdown, ShutdownFairing};
+use store::{MemoryStore, StateStore};
+use types::{
+    Argument, AttachmentAction, Callback, CardActionCallback, Membership, Message as OwnMessage,
+    MessageOut, Person, Webhook,
+};
+pub mod access;
 pub mod adaptive_card;
+#[cfg(feature = "alerts")]
+pub mod alerts;
+#[cfg(feature = "broker")]
+pub mod broker;
+mod dedup;
+pub mod device_auth;
+#[cfg(feature = "broker")]
+pub mod devices;
+pub mod dispatcher;
+pub mod envelope;
+pub mod event_source;
+pub mod events;
+pub mod health;
+pub mod ids;
+pub mod metrics;
+pub mod middleware;
+pub mod notify;
 mod parser;
+pub mod rate_limit;
+pub mod reconnect;
+pub mod reminders;
+pub mod responder;
+pub mod scheduler;
+mod secret;
 pub mod service;
+pub mod sessions;
+pub mod shutdown;
+pub mod store;
 pub mod types;
+pub mod webex_websocket;
 
 // ###################################################################################
 // Client that manages all interaction with the webex API's.
@@ -47,6 +88,12 @@ pub mod types;
 #[derive(Clone)]
 pub struct WebexClient {
     pub bearer_token: String,
+    pub sessions: Sessions,
+    pub store: Arc<dyn StateStore>,
+    pub scheduler: Scheduler,
+    pub shutdown: Shutdown,
+    #[cfg(feature = "broker")]
+    pub devices: devices::DeviceRegistry, // Devices of the broker, see `enable_broker`.
 }
 
 impl WebexClient {
@@ -54,6 +101,12 @@ impl WebexClient {
     pub fn new(token: &str) -> WebexClient {
         WebexClient {
             bearer_token: token.to_string(),
+            sessions: Sessions::new(),
+            store: Arc::new(MemoryStore::new()),
+            scheduler: Scheduler::new(),
+            shutdown: Shutdown::new(),
+            #[cfg(feature = "broker")]
+            devices: devices::DeviceRegistry::new(),
         }
     }
 
@@ -72,19 +125,120 @@ impl WebexClient {
     pub async fn get_message_details(&self, message_id: &String) -> OwnMessage {
         service::get_message_details(&self.bearer_token, message_id).await
     }
+
+    // ------------------------------------------------------------------------------
+    // Retrieve the inputs a user submitted through an adaptive card.
+    // ------------------------------------------------------------------------------
+
+    pub async fn get_attachment_action(
+        &self,
+        action_id: &str,
+    ) -> Result<AttachmentAction, reqwest::Error> {
+        service::get_attachment_action(&self.bearer_token, action_id).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // Retrieve all the information regarding a webex user.
+    // ------------------------------------------------------------------------------
+
+    pub async fn get_person_details(&self, person_id: &str) -> Result<Person, reqwest::Error> {
+        service::get_person_details(&self.bearer_token, person_id).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // Retrieve the details of the bot itself, validating the token.
+    // ------------------------------------------------------------------------------
+
+    pub async fn get_my_details(&self) -> Result<Person, reqwest::Error> {
+        service::get_my_details(&self.bearer_token).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // List the webhooks registered by the bot.
+    // ------------------------------------------------------------------------------
+
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>, reqwest::Error> {
+        service::list_webhooks(&self.bearer_token).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // Wait for the next message the author of `message` posts in the same room.
+    // Only messages received by the bot server that owns this client are seen.
+    // ------------------------------------------------------------------------------
+
+    pub async fn wait_for_reply(
+        &self,
+        message: &OwnMessage,
+        timeout: Duration,
+    ) -> Result<OwnMessage, SessionError> {
+        match (&message.room_id, &message.person_id) {
+            (Some(room_id), Some(person_id)) => {
+                self.sessions.wait_for(room_id, person_id, timeout).await
+            }
+            _ => Err(SessionError::Missing),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Ask a follow-up question in the room of `message` and wait for the answer.
+    // ------------------------------------------------------------------------------
+
+    pub async fn ask(
+        &self,
+        message: &OwnMessage,
+        question: &str,
+        timeout: Duration,
+    ) -> Result<OwnMessage, SessionError> {
+        self.send_message(&MessageOut {
+            room_id: message.room_id.clone(),
+            parent_id: message.parent_id.clone(),
+            markdown: Some(question.to_string()),
+            ..Default::default()
+        })
+        .await;
+        self.wait_for_reply(message, timeout).await
+    }
+
+    // ------------------------------------------------------------------------------
+    // List the memberships of a room, optionally only the ones of a person.
+    // ------------------------------------------------------------------------------
+
+    pub async fn list_memberships(
+        &self,
+        room_id: &str,
+        person_id: Option<&str>,
+    ) -> Result<Vec<Membership>, reqwest::Error> {
+        service::list_memberships(&self.bearer_token, room_id, person_id).await
+    }
 }
 
 // ###################################################################################
 // Server that handdles all incoming bot requests and handles.
 // ###################################################################################
 
-struct WebexBotState {
+#[derive(Clone)]
+pub(crate) struct WebexBotState {
     client: WebexClient,
     parser: Arc<Mutex<Parser>>,
+    middlewares: Arc<Mutex<Vec<Arc<dyn Middleware>>>>,
+    readiness: Arc<Readiness>,
+    workers: WorkerPool,
+    dedup: Arc<Deduplicator>,
+    responder: Arc<ErrorResponder>,
+    card_action: Option<CardActionCallback>,
+    me: Arc<tokio::sync::OnceCell<String>>, // Uuid of the bot, see `Dispatcher::dispatch`.
 }
 
+type Extension = Box<dyn FnOnce(Rocket<Build>) -> Rocket<Build> + Send>;
+
 pub struct WebexBotServer {
     _server: Rocket<Build>,
+    state: WebexBotState,
+    extensions: Vec<Extension>,
+    shutdown_deadline: Duration,
+    worker_config: WorkerConfig,
+    event_source: Box<dyn EventSource>,
+    webhook_url: Option<String>, // Public url of the webhook listener, for readiness.
 }
 
 impl<'a> WebexBotServer {
@@ -95,17 +249,161 @@ impl<'a> WebexBotServer {
     pub fn new(token: &str) -> WebexBotServer {
         WebexBotServer {
             _server: rocket::build()
-                .mount("/", routes![signature, webhook_listener])
-                .mount("/public", FileServer::from("static/"))
-                .manage(WebexBotState {
-                    client: WebexClient::new(token),
-                    parser: Arc::new(Mutex::new(Parser::new())),
-                }),
+                .mount("/", routes![signature])
+                .mount(
+                    "/",
+                    routes![health::healthz, health::readyz, health::metrics],
+                )
+                .mount("/public", FileServer::from("static/")),
+            state: WebexBotState {
+                client: WebexClient::new(token),
+                parser: Arc::new(Mutex::new(Parser::new())),
+                middlewares: Arc::new(Mutex::new(Vec::new())),
+                readiness: Arc::new(Readiness::default()),
+                workers: WorkerPool::default(),
+                dedup: Arc::new(Deduplicator::default()),
+                responder: Arc::new(ErrorResponder::default()),
+                card_action: None,
+                me: Arc::new(tokio::sync::OnceCell::new()),
+            },
+            extensions: Vec::new(),
+            shutdown_deadline: Duration::from_secs(30),
+            worker_config: WorkerConfig::default(),
+            event_source: Transport::default().source(),
+            webhook_url: None,
         }
     }
 
-    pub async fn launch(self) -> Result<Rocket<Ignite>, RocketError> {
-        self._server.launch().await
+    pub async fn launch(mut self) -> Result<Rocket<Ignite>, RocketError> {
+        WorkerPool::start(self.worker_config, &mut self.state);
+
+        let client = self.state.client.clone();
+        tokio::spawn(client.scheduler.clone().run(client.clone()));
+
+        let readiness = self.state.readiness.clone();
+        let webhook_url = self.webhook_url.clone();
+        tokio::spawn(async move { readiness.watch(client, webhook_url).await });
+
+        let server = self
+            .extensions
+            .into_iter()
+            .fold(self._server, |server, extension| extension(server));
+        let server = self
+            .event_source
+            .attach(server, Dispatcher::new(self.state.clone()));
+        server
+            .attach(ShutdownFairing {
+                client: self.state.client.clone(),
+                deadline: self.shutdown_deadline,
+            })
+            .manage(self.state)
+            .launch()
+            .await
+    }
+
+    // ------------------------------------------------------------------------------
+    // Handle to shut the server down gracefully: webhooks are refused, running
+    // handlers and jobs get up to the deadline to finish and websocket clients
+    // are closed. Ctrl-c and SIGTERM do the same.
+    // ------------------------------------------------------------------------------
+
+    pub fn shutdown(&self) -> Shutdown {
+        self.state.client.shutdown.clone()
+    }
+
+    pub fn set_shutdown_deadline(&mut self, deadline: Duration) {
+        self.shutdown_deadline = deadline;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Size the pool running the handlers: number of workers and events each one
+    // can queue. Events of a room are always handled in order by the same worker.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_workers(&mut self, config: WorkerConfig) {
+        self.worker_config = config;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Select how the events reach the bot: webhooks (default) or websocket.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.event_source = transport.source();
+    }
+
+    // ------------------------------------------------------------------------------
+    // Public url Webex posts the webhooks to (e.g. "https://bot.example.com/cats/
+    // futbolito"). Readiness requires an active webhook targeting it; by default
+    // any webhook posting to the listener path counts.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_webhook_url(&mut self, url: &str) {
+        self.webhook_url = Some(url.to_string());
+    }
+
+    // ------------------------------------------------------------------------------
+    // Use a configured or custom event source instead of a built-in transport.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_event_source<S: EventSource + 'static>(&mut self, source: S) {
+        self.event_source = Box::new(source);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Handle the inputs submitted through adaptive cards, whatever the transport.
+    // ------------------------------------------------------------------------------
+
+    pub fn on_card_action(&mut self, callback: CardActionCallback) {
+        self.state.card_action = Some(callback);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Configure the replies to unparsable messages and failed handlers.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_error_responder(&mut self, responder: ErrorResponder) {
+        self.state.responder = Arc::new(responder);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Redelivered events are dropped for the given window. The event ids
+    // live in the state store, plus a local cache of the latest `capacity` ones
+    // (zero disables the deduplication).
+    // ------------------------------------------------------------------------------
+
+    pub fn set_deduplication(&mut self, window: Duration, capacity: usize) {
+        self.state.dedup = Arc::new(Deduplicator::new(window, capacity));
+    }
+
+    // ------------------------------------------------------------------------------
+    // Defer a change to the rocket instance (extra routes, managed state) until
+    // the server is launched.
+    // ------------------------------------------------------------------------------
+
+    fn extend<F: FnOnce(Rocket<Build>) -> Rocket<Build> + Send + 'static>(&mut self, extension: F) {
+        self.extensions.push(Box::new(extension));
+    }
+
+    // ------------------------------------------------------------------------------
+    // Mount the `POST /notify` route, letting other services post messages through
+    // the bot. Callers authenticate with one of the given bearer tokens.
+    // ------------------------------------------------------------------------------
+
+    pub fn enable_notify_api(&mut self, tokens: Vec<String>) {
+        self.extend(move |server| {
+            server
+                .mount("/", routes![notify::notify])
+                .manage(notify::NotifyConfig::new(tokens))
+        });
+    }
+
+    // ------------------------------------------------------------------------------
+    // Replace the in-memory state store shared with the command handlers.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_store(&mut self, store: Arc<dyn StateStore>) {
+        self.state.client.store = store;
     }
 
     // ------------------------------------------------------------------------------
@@ -118,15 +416,163 @@ impl<'a> WebexBotServer {
         args: Vec<Box<dyn Argument>>,
         callback: Callback,
     ) {
-        let server = self
-            ._server
-            .state::<WebexBotState>()
-            .unwrap()
-            .parser
-            .clone();
+        let server = self.state.parser.clone();
         let mut server_unlock = server.lock().await;
         server_unlock.add_command(command, args, callback);
     }
+
+    // ------------------------------------------------------------------------------
+    // Add a natural language route, evaluated after the strict commands. Named
+    // capture groups are delivered to the callback as required arguments.
+    // ------------------------------------------------------------------------------
+
+    pub async fn add_pattern(&'a self, regex: Regex, callback: Callback) {
+        self.add_prioritized_pattern(regex, 0, callback).await;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Add a natural language route with an explicit priority (higher goes first).
+    // ------------------------------------------------------------------------------
+
+    pub async fn add_prioritized_pattern(
+        &'a self,
+        regex: Regex,
+        priority: i32,
+        callback: Callback,
+    ) {
+        let server = self.state.parser.clone();
+        let mut server_unlock = server.lock().await;
+        server_unlock.add_pattern(regex, priority, callback);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Add a prioritized route reported to the middlewares and metrics under the
+    // given command name instead of its regex.
+    // ------------------------------------------------------------------------------
+
+    pub async fn add_named_pattern(
+        &'a self,
+        name: &str,
+        regex: Regex,
+        priority: i32,
+        callback: Callback,
+    ) {
+        let server = self.state.parser.clone();
+        let mut server_unlock = server.lock().await;
+        server_unlock.add_named_pattern(name, regex, priority, callback);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Set the callback for messages that match no command nor pattern.
+    // ------------------------------------------------------------------------------
+
+    pub async fn set_default(&'a self, callback: Callback) {
+        let server = self.state.parser.clone();
+        let mut server_unlock = server.lock().await;
+        server_unlock.set_default(callback);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Mount the Alertmanager (`POST /alerts/alertmanager`) and Grafana
+    // (`POST /alerts/grafana`) receivers, posting alerts as cards to the rooms
+    // selected by the router.
+    // ------------------------------------------------------------------------------
+
+    #[cfg(feature = "alerts")]
+    pub fn enable_alert_bridge(&mut self, router: alerts::AlertRouter) {
+        self.extend(move |server| {
+            server
+                .mount("/", routes![alerts::alertmanager, alerts::grafana])
+                .manage(router)
+        });
+    }
+
+    // ------------------------------------------------------------------------------
+    // Serve the embedded devices from the bot: the broker protocol routes are
+    // mounted on the server and the device websockets get their own listener.
+    // The handlers reach the devices through `client.devices`.
+    // ------------------------------------------------------------------------------
+
+    #[cfg(feature = "broker")]
+    pub fn enable_broker(&mut self, broker: broker::Broker) {
+        self.state.client.devices = broker.registry().clone();
+        let shutdown = self.state.client.shutdown.clone();
+        self.extend(move |server| {
+            tokio::spawn(broker.clone().serve(Some(shutdown)));
+            server
+                .mount(
+                    "/",
+                    routes![broker::register, broker::unregister, broker::publish],
+                )
+                .manage(broker)
+        });
+    }
+
+    // ------------------------------------------------------------------------------
+    // Enable the built-in `devices [group]` command, listing the devices registered
+    // with the broker, their groups and presence.
+    // ------------------------------------------------------------------------------
+
+    #[cfg(feature = "broker")]
+    pub async fn enable_devices_command(&'a self) {
+        self.add_named_pattern(
+            devices::COMMAND,
+            devices::pattern(),
+            devices::PRIORITY,
+            devices::list_devices,
+        )
+        .await;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Register a job the scheduler can run, then schedule it by name either here or
+    // from any handler through `client.scheduler`.
+    // ------------------------------------------------------------------------------
+
+    pub fn add_job(&'a self, name: &str, job: Job) {
+        self.state.client.scheduler.add_job(name, job);
+    }
+
+    pub fn schedule(
+        &'a self,
+        job: &str,
+        trigger: Trigger,
+        payload: serde_json::Value,
+        persistent: bool,
+    ) -> Result<String, SchedulerError> {
+        self.state
+            .client
+            .scheduler
+            .schedule_at_launch(job, trigger, payload, persistent)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Enable the built-in `remind` command. Times without an explicit date or
+    // delay (e.g. "tomorrow 9am") are read in the given IANA time zone.
+    // ------------------------------------------------------------------------------
+
+    pub async fn enable_reminders(&'a self, timezone: chrono_tz::Tz) {
+        self.add_job(reminders::JOB, reminders::fire);
+        reminders::Config { timezone }.apply(&self.state.client.scheduler);
+        self.add_named_pattern(
+            reminders::COMMAND,
+            reminders::pattern(),
+            reminders::PRIORITY,
+            reminders::remind,
+        )
+        .await;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Append a middleware to the chain wrapped around every command dispatch.
+    // Middlewares run in the order they were added.
+    // ------------------------------------------------------------------------------
+
+    pub async fn add_middleware<M: Middleware + 'static>(&'a self, middleware: M) {
+        let middlewares = self.state.middlewares.clone();
+        let mut middlewares_unlock = middlewares.lock().await;
+        middlewares_unlock.push(Arc::new(middleware));
+    }
 }
 
 // #########################################################################################
@@ -138,53 +584,25 @@ pub fn signature() -> &'static str {
     "WebexBot Server"
 }
 
-// #########################################################################################
-// Webhook root listener.
-// #########################################################################################
-
-#[post("/cats/futbolito", format = "json", data = "<data>")]
-async fn webhook_listener(
-    data: Json<Response<MessageEventResponse>>,
-    state: &State<WebexBotState>,
-) -> () {
-    // Retrieve message details as this contains the text for the bot call.
-    let detailed_message_info = state.client.get_message_details(&data.data.id).await;
-
-    // Log the detailed message contents.
-    log::info!("[Message info]: {:?}\n", &detailed_message_info);
-
-    // Parse the actual plain text data/message.
-    let raw_message = detailed_message_info.text.clone().unwrap();
-    let parser = state.parser.clone();
-    let parsed_value_unlock = parser.lock().await;
-    let parsed_value = parsed_value_unlock.parse(raw_message);
-
-    // Check if the match was successful and execute the callback.
-    match parsed_value {
-        Ok(v) => {
-            (v.callback)(
-                state.client.clone(),
-                detailed_message_info,
-                v.required_arguments,
-                v.optional_arguments,
-            )
-            .await
-        }
-        Err(e) => {
-            error!("{}", e);
-        }
-    }
-}
-
 // ###################################################################################
 // WebSocket Client.
 // ###################################################################################
 
+#[derive(Clone)]
 pub struct WebSocketClient {
     host: String,
     port: u16,
     user_id: u16,
     subscription_groups: Vec<String>,
+    metadata: HashMap<String, String>, // Sent along the registration.
+    tls: bool,                         // https for the broker requests, wss for the socket.
+    auth: BrokerAuth,                  // Authentication of register and publish.
+    roots: Vec<Vec<u8>>,               // Custom CA roots (PEM) trusted besides the system ones.
+    connector: Option<native_tls::TlsConnector>, // Trusts the custom CA roots, if any.
+    shutdown: Option<Shutdown>,
+    endpoint: Arc<std::sync::Mutex<Option<String>>>, // Last registration endpoint, to register again.
+    policy: ReconnectPolicy,
+    on_state: Option<StateCallback>,
     _client: Client,
     _headers: HeaderMap,
 }
@@ -212,35 +630,119 @@ impl WebSocketClient {
             port,
             user_id,
             subscription_groups,
+            metadata: HashMap::new(),
+            tls: false,
+            auth: BrokerAuth::None,
+            roots: Vec::new(),
+            connector: None,
+            shutdown: None,
+            endpoint: Arc::new(std::sync::Mutex::new(None)),
+            policy: ReconnectPolicy::default(),
+            on_state: None,
             _client: Client::new(),
             _headers: headers,
         }
     }
 
+    // ----------------------------------------------------------------------------
+    // Describe the device to the broker (firmware, location...) at registration.
+    // ----------------------------------------------------------------------------
+
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
+
+    // ----------------------------------------------------------------------------
+    // Talk to the broker over https and connect the socket over wss, upgrading the
+    // ws:// urls it hands out.
+    // ----------------------------------------------------------------------------
+
+    pub fn set_tls(&mut self, enabled: bool) {
+        self.tls = enabled;
+    }
+
+    // ----------------------------------------------------------------------------
+    // Trust a CA (PEM) besides the system roots, e.g. for a self-signed broker.
+    // Every CA added is trusted.
+    // ----------------------------------------------------------------------------
+
+    pub fn add_root_certificate(&mut self, pem: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut roots = self.roots.clone();
+        roots.push(pem.to_vec());
+
+        let mut client = Client::builder();
+        let mut connector = native_tls::TlsConnector::builder();
+        for root in &roots {
+            client = client.add_root_certificate(reqwest::Certificate::from_pem(root)?);
+            connector.add_root_certificate(native_tls::Certificate::from_pem(root)?);
+        }
+
+        self._client = client.build()?;
+        self.connector = Some(connector.build()?);
+        self.roots = roots;
+        Ok(())
+    }
+
+    // ----------------------------------------------------------------------------
+    // Authenticate the register and publish requests with a bearer token or an
+    // HMAC signature (see `BrokerAuth`).
+    // ----------------------------------------------------------------------------
+
+    pub fn set_auth(&mut self, auth: BrokerAuth) {
+        self.auth = auth;
+    }
+
+    // ----------------------------------------------------------------------------
+    // Close the websocket connections when the bot server shuts down.
+    // ----------------------------------------------------------------------------
+
+    pub fn set_shutdown(&mut self, shutdown: Shutdown) {
+        self.shutdown = Some(shutdown);
+    }
+
+    // ----------------------------------------------------------------------------
+    // Backoff and keepalive settings of the connection.
+    // ----------------------------------------------------------------------------
+
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.policy = policy;
+    }
+
+    // ----------------------------------------------------------------------------
+    // Called on every connection state change, e.g. to alert when a device link
+    // is down.
+    // ----------------------------------------------------------------------------
+
+    pub fn on_state_change<F: Fn(&ConnectionState) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.on_state = Some(Arc::new(callback));
+    }
+
     // ----------------------------------------------------------------------------
     // Retrieve a new websocket url from the server.
     // ----------------------------------------------------------------------------
-    pub async fn register(&self, endpoint: &str) -> RegisterResponse {
+    pub async fn register(&self, endpoint: &str) -> Result<RegisterResponse, reqwest::Error> {
+        *self.endpoint.lock().unwrap() = Some(endpoint.to_string());
+
         let response = self
-            ._client
-            .post(format!("http://{}:{}/{}", self.host, self.port, endpoint))
-            .headers(self._headers.clone())
-            .json(&Register {
-                user_id: self.user_id,
-                groups: self.subscription_groups.clone(),
-            })
-            .send()
-            .await
-            .unwrap();
+            .post(
+                endpoint,
+                &Register {
+                    user_id: self.user_id,
+                    groups: self.subscription_groups.clone(),
+                    metadata: self.metadata.clone(),
+                },
+            )
+            .await?;
 
         self.review_status(&response);
 
-        let message = response
+        response
+            .error_for_status()?
             .json::<RegisterResponse>()
             .await
-            .expect("failed to convert struct from json");
-
-        return message;
     }
 
     // ----------------------------------------------------------------------------
@@ -253,27 +755,50 @@ impl WebSocketClient {
         user_id: u16,
         group: String,
         message: serde_json::Value,
-    ) {
+    ) -> Result<(), reqwest::Error> {
         let response = self
-            ._client
-            .post(format!("http://{}:{}/{}", self.host, self.port, endpoint))
+            .post(
+                endpoint,
+                &Publish {
+                    user_id,
+                    group,
+                    message: message.to_string(),
+                },
+            )
+            .await?;
+
+        self.review_status(&response);
+        response.error_for_status()?;
+        Ok(())
+    }
+
+    // ----------------------------------------------------------------------------
+    // Authenticated request to the broker, signed over its path and the exact
+    // body sent.
+    // ----------------------------------------------------------------------------
+
+    async fn post<T: serde::Serialize>(
+        &self,
+        endpoint: &str,
+        payload: &T,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let scheme = if self.tls { "https" } else { "http" };
+        let path = format!("/{}", endpoint);
+        let body = serde_json::to_vec(payload).expect("broker payloads are serializable");
+
+        self._client
+            .post(format!("{}://{}:{}{}", scheme, self.host, self.port, path))
             .headers(self._headers.clone())
-            .json(&Publish {
-                user_id: user_id,
-                group,
-                message: message.to_string(),
-            })
+            .headers(self.auth.headers("POST", &path, &body))
+            .body(body)
             .send()
             .await
-            .unwrap();
-
-        self.review_status(&response);
     }
 
     // ----------------------------------------------------------------------------
     // Review the status for a given response.
     // ----------------------------------------------------------------------------
-    pub fn review_status(&self, response: &reqwest::Response) -> () {
+    pub fn review_status(&self, response: &reqwest::Response) {
         match response.status() {
             reqwest::StatusCode::OK => {
                 log::debug!("Succesful request: {:?}", response)
@@ -288,80 +813,78 @@ impl WebSocketClient {
     }
 
     // ----------------------------------------------------------------------------
-    // Initialize WebSocket Client.
+    // Initialize WebSocket Client. Messages written to the returned sender are
+    // forwarded to the socket, the receiver yields the ones the server sends.
+    // The connection is kept alive and re-established with backoff when it
+    // drops, registering again after a failed attempt.
     // ----------------------------------------------------------------------------
     pub async fn start_ws_client(
         &self,
         registration_url: String,
     ) -> Result<(Sender<Message>, Receiver<Message>), Box<dyn Error>> {
         // Parse the registration URL as of a URL type.
-        let url = url::Url::parse(&registration_url).unwrap();
+        let url = url::Url::parse(&registration_url)?;
         debug!("Parsed registration string: {}", url);
 
         // Create channels to send and receive messages
-        let (sender, receiver) = mpsc::channel(32);
-
-        // let (stdin_tx, stdin_rx) = futures_channel::mpsc::unbounded();
-        // tokio::spawn(read_stdin(stdin_tx));
-
-        let (ws_stream, _) = connect_async(url).await.expect("Failed to connect");
-        info!("WebSocket handshake has been successfully completed");
-
-        // Split the WebSocket into sender and receiver.
-        let (ws_sender, ws_receiver) = ws_stream.split();
-
-        // Spawn a task to receive messages and forward them to the receiver channel
-        tokio::spawn(receive_messages(ws_receiver, sender.clone()));
-
-        // Spawn a task to send messages
-        tokio::spawn(send_messages(ws_sender));
+        let (inbound_sender, inbound_receiver) = mpsc::channel(32);
+        let (outbound_sender, outbound_receiver) = mpsc::channel(32);
+
+        let client = self.clone();
+        let url = Arc::new(std::sync::Mutex::new(url.to_string()));
+        let connect: Connect = Box::new(move |register| {
+            let client = client.clone();
+            let url = url.clone();
+            Box::pin(async move { client.connect(&url, register).await })
+        });
+        tokio::spawn(supervise(
+            connect,
+            self.policy.clone(),
+            self.on_state.clone(),
+            self.shutdown.clone(),
+            inbound_sender,
+            Some(outbound_receiver),
+        ));
+
+        Ok((outbound_sender, inbound_receiver))
+    }
 
-        Ok((sender, receiver))
+    // ----------------------------------------------------------------------------
+    // Same connection, exchanging typed envelopes: `DeviceChannel::request` sends
+    // one and awaits the device reply carrying its id as correlation id.
+    // ----------------------------------------------------------------------------
+    pub async fn start_channel(
+        &self,
+        registration_url: String,
+    ) -> Result<(DeviceChannel, Receiver<Message>), Box<dyn Error>> {
+        let (sender, receiver) = self.start_ws_client(registration_url).await?;
+        Ok(DeviceChannel::new(sender, receiver))
     }
-}
 
-// ----------------------------------------------------------------------------
-// Function to receive messages from the WebSocket and forward them to the channel.
-// ----------------------------------------------------------------------------
-async fn receive_messages(
-    ws_stream: SplitStream<
-        WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
-    >,
-    sender: Sender<Message>,
-) {
-    let mut ws_stream = ws_stream;
-
-    while let Some(message) = ws_stream.next().await {
-        match message {
-            Ok(msg) => {
-                // Forward the received message to the channel
-                if sender.send(msg).await.is_err() {
-                    eprintln!("Receiver dropped, closing connection.");
-                    return;
-                }
-            }
-            Err(e) => {
-                eprintln!("Error receiving message: {}", e);
+    async fn connect(
+        &self,
+        url: &std::sync::Mutex<String>,
+        register: bool,
+    ) -> Result<WsStream, String> {
+        let endpoint = self.endpoint.lock().unwrap().clone();
+        if let (true, Some(endpoint)) = (register, endpoint) {
+            let registration = self.register(&endpoint).await.map_err(|e| e.to_string())?;
+            *url.lock().unwrap() = registration.url;
+        }
+
+        let mut url = url.lock().unwrap().clone();
+        if self.tls {
+            if let Some(rest) = url.strip_prefix("ws://") {
+                url = format!("wss://{}", rest);
             }
         }
-    }
-}
 
-// ----------------------------------------------------------------------------
-// Function to send messages via the WebSocket.
-// ----------------------------------------------------------------------------
-pub async fn send_messages(
-    mut ws_stream: SplitSink<
-        WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
-        tokio_tungstenite::tungstenite::Message,
-    >,
-) {
-    // This could be a loop where you send messages as needed
-    // For the example, we're just sending one message and then exiting
-    let message = Message::Text("Hello, WebSocket Server!".into());
-
-    if let Err(e) = ws_stream.send(message).await {
-        eprintln!("Error sending message: {}", e);
+        let connector = self.connector.clone().map(Connector::NativeTls);
+        let (ws_stream, _) = connect_async_tls_with_config(url.as_str(), None, false, connector)
+            .await
+            .map_err(|e| e.to_string())?;
+        info!("WebSocket handshake has been successfully completed");
+        Ok(ws_stream)
     }
 }
 
@@ -0,0 +1,245 @@
+// std.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+// Own.
+use crate::middleware::Outcome;
+
+// ###################################################################
+// Latency histogram with fixed buckets (seconds).
+// ###################################################################
+
+#[derive(Default, Clone)]
+struct Histogram {
+    buckets: [u64; Histogram::BOUNDS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    const BOUNDS: [f64; 11] = [
+        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+    ];
+
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(Self::BOUNDS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+// ###################################################################
+// Process wide metrics, rendered in the Prometheus text format.
+// ###################################################################
+
+#[derive(Default)]
+pub struct Metrics {
+    commands: Mutex<BTreeMap<(String, &'static str), u64>>,
+    latency: Mutex<BTreeMap<String, Histogram>>,
+    api_calls: Mutex<BTreeMap<u16, u64>>,
+    rate_limited: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    pub fn get_instance() -> &'static Metrics {
+        static INSTANCE: OnceLock<Metrics> = OnceLock::new();
+        INSTANCE.get_or_init(Metrics::default)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Record a dispatched command, its outcome and how long the chain took.
+    // ------------------------------------------------------------------------------
+
+    pub fn record_command(&self, command: &str, outcome: &Outcome, elapsed: Duration) {
+        let outcome = match outcome {
+            Outcome::Handled => "handled",
+            Outcome::Reply(_) => "replied",
+            Outcome::Dropped => "dropped",
+            Outcome::Failed(_) => "failed",
+        };
+        *self
+            .commands
+            .lock()
+            .unwrap()
+            .entry((command.to_string(), outcome))
+            .or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap()
+            .entry(command.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    // ------------------------------------------------------------------------------
+    // Record a Webex API call by HTTP status.
+    // ------------------------------------------------------------------------------
+
+    pub fn record_api_call(&self, status: u16) {
+        *self.api_calls.lock().unwrap().entry(status).or_default() += 1;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Record a throttled invocation by limit scope (person, room or command).
+    // ------------------------------------------------------------------------------
+
+    pub fn record_rate_limited(&self, scope: &'static str) {
+        *self.rate_limited.lock().unwrap().entry(scope).or_default() += 1;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Render every metric in the Prometheus text exposition format.
+    // ------------------------------------------------------------------------------
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP webex_bot_command_invocations_total Commands dispatched by outcome.\n",
+        );
+        out.push_str("# TYPE webex_bot_command_invocations_total counter\n");
+        for ((command, outcome), count) in self.commands.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "webex_bot_command_invocations_total{{command=\"{}\",outcome=\"{}\"}} {}",
+                escape(command),
+                outcome,
+                count
+            );
+        }
+
+        out.push_str(
+            "# HELP webex_bot_handler_duration_seconds Time spent running the command handlers.\n",
+        );
+        out.push_str("# TYPE webex_bot_handler_duration_seconds histogram\n");
+        for (command, histogram) in self.latency.lock().unwrap().iter() {
+            let command = escape(command);
+            for (bound, count) in Histogram::BOUNDS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "webex_bot_handler_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                    command, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "webex_bot_handler_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                command, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "webex_bot_handler_duration_seconds_sum{{command=\"{}\"}} {}",
+                command, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "webex_bot_handler_duration_seconds_count{{command=\"{}\"}} {}",
+                command, histogram.count
+            );
+        }
+
+        out.push_str("# HELP webex_api_requests_total Webex API calls by HTTP status.\n");
+        out.push_str("# TYPE webex_api_requests_total counter\n");
+        for (status, count) in self.api_calls.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "webex_api_requests_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+
+        out.push_str(
+            "# HELP webex_bot_rate_limited_total Throttled command invocations by limit scope.\n",
+        );
+        out.push_str("# TYPE webex_bot_rate_limited_total counter\n");
+        for (scope, count) in self.rate_limited.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "webex_bot_rate_limited_total{{scope=\"{}\"}} {}",
+                scope, count
+            );
+        }
+
+        out
+    }
+}
+
+// Escape a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        for seconds in [0.5, 2.0, 20.0] {
+            metrics.record_command(
+                "status",
+                &Outcome::Handled,
+                Duration::from_secs_f64(seconds),
+            );
+        }
+        let out = metrics.render();
+
+        for (le, count) in [
+            ("0.25", 0),
+            ("0.5", 1),
+            ("1", 1),
+            ("2.5", 2),
+            ("10", 2),
+            ("+Inf", 3),
+        ] {
+            let line = format!(
+                "webex_bot_handler_duration_seconds_bucket{{command=\"status\",le=\"{}\"}} {}\n",
+                le, count
+            );
+            assert!(out.contains(&line), "missing {:?} in\n{}", line, out);
+        }
+        assert!(out.contains("webex_bot_handler_duration_seconds_sum{command=\"status\"} 22.5\n"));
+        assert!(out.contains("webex_bot_handler_duration_seconds_count{command=\"status\"} 3\n"));
+        assert!(out.contains(
+            "webex_bot_command_invocations_total{command=\"status\",outcome=\"handled\"} 3\n"
+        ));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::default();
+        metrics.record_command("say \"hi\"\\\nbye", &Outcome::Dropped, Duration::ZERO);
+        let out = metrics.render();
+        assert!(out.contains(
+            "webex_bot_command_invocations_total{command=\"say \\\"hi\\\"\\\\\\nbye\",outcome=\"dropped\"} 1\n"
+        ));
+        assert!(!out.contains("\nbye"));
+    }
+
+    #[test]
+    fn counters_are_rendered_by_label() {
+        let metrics = Metrics::default();
+        metrics.record_api_call(200);
+        metrics.record_api_call(200);
+        metrics.record_api_call(429);
+        metrics.record_rate_limited("person");
+        let out = metrics.render();
+        assert!(out.contains("webex_api_requests_total{status=\"200\"} 2\n"));
+        assert!(out.contains("webex_api_requests_total{status=\"429\"} 1\n"));
+        assert!(out.contains("webex_bot_rate_limited_total{scope=\"person\"} 1\n"));
+    }
+}
@@ -0,0 +1,317 @@
+// std.
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+
+// Future.
+use futures_util::FutureExt;
+
+// logging.
+use log::info;
+
+// Own.
+use crate::dispatcher::panic_message;
+use crate::events::Event;
+use crate::types::{
+    ArgTuple, AttachmentAction, Callback, CardActionCallback, HandlerError, HandlerResult, Message,
+    MessageOut,
+};
+use crate::WebexClient;
+
+// ###################################################################
+// Boxed future returned by every middleware.
+// ###################################################################
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// ###################################################################
+// Command invocation travelling through the middleware chain.
+// ###################################################################
+
+pub struct Request {
+    pub client: WebexClient,
+    pub message: Message,
+    pub command: String, // Resolved command name, pattern, "default" or "card_action".
+    pub required_arguments: ArgTuple,
+    pub optional_arguments: ArgTuple,
+}
+
+// ###################################################################
+// Result of running a command through the chain.
+// ###################################################################
+
+#[derive(Debug)]
+pub enum Outcome {
+    Handled,              // The callback ran.
+    Reply(MessageOut),    // Short-circuited, the server posts this message instead.
+    Dropped,              // Short-circuited silently.
+    Failed(HandlerError), // The callback returned an error or panicked.
+}
+
+impl Outcome {
+    // ------------------------------------------------------------------------------
+    // Short-circuit with a plain text reply to the room of the original message.
+    // ------------------------------------------------------------------------------
+
+    pub fn reply(message: &Message, text: &str) -> Outcome {
+        Outcome::Reply(MessageOut {
+            room_id: message.room_id.clone(),
+            text: Some(text.to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+// ###################################################################
+// Middleware trait. Call `next.run(request)` to continue the chain.
+// ###################################################################
+
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Outcome>;
+
+    // Look at a queued event before its message is retrieved from Webex, with only
+    // its actor and room known. Return an outcome to short-circuit it there.
+    fn screen(&self, _event: &Event) -> Option<Outcome> {
+        None
+    }
+}
+
+// ###################################################################
+// Callback ending the chain: a command, or the card action handler for
+// adaptive card submissions (the request command is "card_action").
+// ###################################################################
+
+pub(crate) enum Handler {
+    Command(Callback),
+    CardAction(CardActionCallback, AttachmentAction),
+}
+
+// ###################################################################
+// Remainder of the chain, ending with the handler.
+// ###################################################################
+
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    handler: Handler,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middlewares: &'a [Arc<dyn Middleware>], handler: Handler) -> Next<'a> {
+        Next {
+            middlewares,
+            handler,
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Hand the request over to the next middleware or to the handler itself.
+    // ------------------------------------------------------------------------------
+
+    pub fn run(self, request: Request) -> BoxFuture<'a, Outcome> {
+        match self.middlewares.split_first() {
+            Some((current, rest)) => current.handle(request, Next::new(rest, self.handler)),
+            None => Box::pin(async move {
+                let result = AssertUnwindSafe(call(self.handler, request))
+                    .catch_unwind()
+                    .await;
+
+                match result {
+                    Ok(Ok(())) => Outcome::Handled,
+                    Ok(Err(e)) => Outcome::Failed(e),
+                    Err(panic) => {
+                        Outcome::Failed(format!("panicked: {}", panic_message(&panic)).into())
+                    }
+                }
+            }),
+        }
+    }
+}
+
+async fn call(handler: Handler, request: Request) -> HandlerResult {
+    match handler {
+        Handler::Command(callback) => {
+            callback(
+                request.client,
+                request.message,
+                request.required_arguments,
+                request.optional_arguments,
+            )
+            .await
+        }
+        Handler::CardAction(callback, action) => callback(request.client, action).await,
+    }
+}
+
+// ###################################################################
+// Built-in middleware that logs every invocation and its duration.
+// ###################################################################
+
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Outcome> {
+        Box::pin(async move {
+            let command = request.command.clone();
+            let person = request.message.person_email.clone().unwrap_or_default();
+            let start = Instant::now();
+
+            let outcome = next.run(request).await;
+
+            info!(
+                "[Command]: {} by {} finished in {:?} ({:?})",
+                command,
+                person,
+                start.elapsed(),
+                outcome
+            );
+            outcome
+        })
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    type Trace = Arc<Mutex<Vec<String>>>;
+    type HandlerFuture = Pin<Box<dyn Future<Output = HandlerResult> + Send + Sync>>;
+
+    // Records when the request goes through and when the outcome comes back.
+    struct Tag(&'static str, Trace);
+
+    impl Middleware for Tag {
+        fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Outcome> {
+            Box::pin(async move {
+                self.1.lock().unwrap().push(format!("{} in", self.0));
+                let outcome = next.run(request).await;
+                self.1.lock().unwrap().push(format!("{} out", self.0));
+                outcome
+            })
+        }
+    }
+
+    struct Refuse(Option<&'static str>);
+
+    impl Middleware for Refuse {
+        fn handle<'a>(&'a self, request: Request, _next: Next<'a>) -> BoxFuture<'a, Outcome> {
+            Box::pin(async move {
+                match self.0 {
+                    Some(text) => Outcome::reply(&request.message, text),
+                    None => Outcome::Dropped,
+                }
+            })
+        }
+    }
+
+    fn request() -> Request {
+        Request {
+            client: WebexClient::new("token"),
+            message: Message {
+                room_id: Some("room".to_string()),
+                ..Default::default()
+            },
+            command: "deploy".to_string(),
+            required_arguments: Vec::new(),
+            optional_arguments: Vec::new(),
+        }
+    }
+
+    fn ok(_: WebexClient, _: Message, _: ArgTuple, _: ArgTuple) -> HandlerFuture {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn fails(_: WebexClient, _: Message, _: ArgTuple, _: ArgTuple) -> HandlerFuture {
+        Box::pin(async { Err("no such service".into()) })
+    }
+
+    fn panics(_: WebexClient, _: Message, _: ArgTuple, _: ArgTuple) -> HandlerFuture {
+        Box::pin(async { panic!("boom") })
+    }
+
+    fn card_action(_: WebexClient, action: AttachmentAction) -> HandlerFuture {
+        Box::pin(async move {
+            match action.id.as_str() {
+                "action" => Ok(()),
+                other => Err(format!("unexpected action {}", other).into()),
+            }
+        })
+    }
+
+    #[rocket::async_test]
+    async fn middlewares_wrap_the_callback_in_order() {
+        let trace = Trace::default();
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Tag("first", trace.clone())),
+            Arc::new(Tag("second", trace.clone())),
+        ];
+
+        let outcome = Next::new(&middlewares, Handler::Command(ok))
+            .run(request())
+            .await;
+        assert!(matches!(outcome, Outcome::Handled));
+        assert_eq!(
+            *trace.lock().unwrap(),
+            vec!["first in", "second in", "second out", "first out"]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn middlewares_can_short_circuit_the_chain() {
+        for (refuse, replied) in [(Refuse(Some("No.")), true), (Refuse(None), false)] {
+            let trace = Trace::default();
+            let middlewares: Vec<Arc<dyn Middleware>> = vec![
+                Arc::new(Tag("outer", trace.clone())),
+                Arc::new(refuse),
+                Arc::new(Tag("inner", trace.clone())),
+            ];
+
+            // The callback would fail the chain if it ran.
+            let outcome = Next::new(&middlewares, Handler::Command(panics))
+                .run(request())
+                .await;
+            match outcome {
+                Outcome::Reply(reply) if replied => {
+                    assert_eq!(reply.room_id.as_deref(), Some("room"));
+                    assert_eq!(reply.text.as_deref(), Some("No."));
+                }
+                Outcome::Dropped if !replied => (),
+                other => panic!("unexpected outcome {:?}", other),
+            }
+            assert_eq!(*trace.lock().unwrap(), vec!["outer in", "outer out"]);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn failing_callbacks_are_reported() {
+        let outcome = Next::new(&[], Handler::Command(fails)).run(request()).await;
+        assert!(matches!(outcome, Outcome::Failed(e) if e.to_string() == "no such service"));
+
+        let outcome = Next::new(&[], Handler::Command(panics))
+            .run(request())
+            .await;
+        assert!(matches!(outcome, Outcome::Failed(e) if e.to_string() == "panicked: boom"));
+    }
+
+    #[rocket::async_test]
+    async fn card_actions_end_the_chain_too() {
+        let trace = Trace::default();
+        let middlewares: Vec<Arc<dyn Middleware>> = vec![Arc::new(Tag("only", trace.clone()))];
+        let action = AttachmentAction {
+            id: "action".to_string(),
+            ..Default::default()
+        };
+
+        let outcome = Next::new(&middlewares, Handler::CardAction(card_action, action))
+            .run(request())
+            .await;
+        assert!(matches!(outcome, Outcome::Handled));
+        assert_eq!(*trace.lock().unwrap(), vec!["only in", "only out"]);
+    }
+}
@@ -0,0 +1,271 @@
+// Rocket.
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{post, State};
+
+// serde.
+use serde::{Deserialize, Serialize};
+
+// logging.
+use log::{info, warn};
+
+// Own.
+use crate::adaptive_card::AdaptiveCard;
+use crate::secret::constant_eq;
+use crate::types::{Attachment, MessageOut};
+use crate::WebexBotState;
+
+// ###################################################################
+// Outbound notification API configuration.
+// ###################################################################
+
+pub(crate) struct NotifyConfig {
+    tokens: Vec<String>,
+}
+
+impl NotifyConfig {
+    pub fn new(tokens: Vec<String>) -> Self {
+        NotifyConfig { tokens }
+    }
+
+    // Compare in constant time, so the tokens can't be guessed by timing.
+    fn is_valid(&self, token: &str) -> bool {
+        self.tokens
+            .iter()
+            .any(|expected| constant_eq(expected.as_bytes(), token.as_bytes()))
+    }
+}
+
+// ###################################################################
+// Request guard checking the bearer token of the caller.
+// ###################################################################
+
+pub(crate) struct NotifyAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for NotifyAuth {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = match request.rocket().state::<NotifyConfig>() {
+            Some(config) => config,
+            None => return Outcome::Failure((Status::NotFound, "Notify API disabled")),
+        };
+
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "));
+
+        match token {
+            Some(token) if config.is_valid(token) => Outcome::Success(NotifyAuth),
+            _ => {
+                warn!("Rejected notification with an invalid token.");
+                Outcome::Failure((Status::Unauthorized, "Invalid token"))
+            }
+        }
+    }
+}
+
+// ###################################################################
+// Notification request and response payloads.
+// ###################################################################
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub room_id: Option<String>,
+    pub to_person_id: Option<String>,
+    pub to_person_email: Option<String>,
+    pub text: Option<String>,
+    pub markdown: Option<String>,
+    pub card: Option<AdaptiveCard>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotifyResponse {
+    pub id: String, // Id of the created message.
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotifyError {
+    pub error: String,
+}
+
+impl Notification {
+    const MAX_LENGTH: usize = 7439;
+    const CARD_CONTENT_TYPE: &'static str = "application/vnd.microsoft.card.adaptive";
+
+    // ------------------------------------------------------------------------------
+    // Check the payload and turn it into the message to be sent.
+    // ------------------------------------------------------------------------------
+
+    pub fn into_message(self) -> Result<MessageOut, String> {
+        let destinations = [&self.room_id, &self.to_person_id, &self.to_person_email]
+            .iter()
+            .filter(|destination| destination.is_some())
+            .count();
+        if destinations != 1 {
+            return Err(
+                "Exactly one of roomId, toPersonId or toPersonEmail is required.".to_string(),
+            );
+        }
+
+        if self.text.is_none() && self.markdown.is_none() && self.card.is_none() {
+            return Err("One of text, markdown or card is required.".to_string());
+        }
+
+        for content in [&self.text, &self.markdown].into_iter().flatten() {
+            if content.len() > Self::MAX_LENGTH {
+                return Err(format!(
+                    "Messages can't be longer than {} bytes.",
+                    Self::MAX_LENGTH
+                ));
+            }
+        }
+
+        // Clients that can't render cards show the text instead.
+        let text = match (&self.text, &self.markdown, &self.card) {
+            (None, None, Some(card)) => Some(
+                card.fallback_text
+                    .clone()
+                    .unwrap_or_else(|| "Adaptive card".to_string()),
+            ),
+            _ => self.text,
+        };
+
+        Ok(MessageOut {
+            room_id: self.room_id,
+            to_person_id: self.to_person_id,
+            to_person_email: self.to_person_email,
+            text,
+            markdown: self.markdown,
+            attachments: self.card.map(|card| {
+                vec![Attachment {
+                    content_type: Self::CARD_CONTENT_TYPE.to_string(),
+                    content: card,
+                }]
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+// #########################################################################################
+// Post a message on behalf of another service.
+// #########################################################################################
+
+#[post("/notify", format = "json", data = "<data>")]
+pub(crate) async fn notify(
+    _auth: NotifyAuth,
+    data: Json<Notification>,
+    state: &State<WebexBotState>,
+) -> Result<Json<NotifyResponse>, (Status, Json<NotifyError>)> {
+    let message = data
+        .into_inner()
+        .into_message()
+        .map_err(|error| (Status::BadRequest, Json(NotifyError { error })))?;
+
+    let sent = state.client.send_message(&message).await;
+    match sent.id {
+        Some(id) => {
+            info!("[Notification]: sent message {}", id);
+            Ok(Json(NotifyResponse { id }))
+        }
+        None => Err((
+            Status::BadGateway,
+            Json(NotifyError {
+                error: "Webex did not accept the message.".to_string(),
+            }),
+        )),
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            room_id: Some("room".to_string()),
+            to_person_id: None,
+            to_person_email: None,
+            text: Some("Deploy finished.".to_string()),
+            markdown: None,
+            card: None,
+        }
+    }
+
+    #[test]
+    fn exactly_one_destination_is_required() {
+        let mut none = notification();
+        none.room_id = None;
+        assert!(none.into_message().unwrap_err().contains("Exactly one"));
+
+        let mut two = notification();
+        two.to_person_email = Some("alice@example.com".to_string());
+        assert!(two.into_message().unwrap_err().contains("Exactly one"));
+
+        let mut person = notification();
+        person.room_id = None;
+        person.to_person_id = Some("alice".to_string());
+        let message = person.into_message().unwrap();
+        assert_eq!(message.room_id, None);
+        assert_eq!(message.to_person_id.as_deref(), Some("alice"));
+        assert_eq!(message.text.as_deref(), Some("Deploy finished."));
+    }
+
+    #[test]
+    fn some_content_is_required() {
+        let mut empty = notification();
+        empty.text = None;
+        assert!(empty.into_message().unwrap_err().contains("One of text"));
+    }
+
+    #[test]
+    fn content_is_limited_in_length() {
+        let mut longest = notification();
+        longest.text = Some("a".repeat(Notification::MAX_LENGTH));
+        assert!(longest.into_message().is_ok());
+
+        let mut text = notification();
+        text.text = Some("a".repeat(Notification::MAX_LENGTH + 1));
+        assert!(text.into_message().unwrap_err().contains("longer than"));
+
+        let mut markdown = notification();
+        markdown.markdown = Some("a".repeat(Notification::MAX_LENGTH + 1));
+        assert!(markdown.into_message().unwrap_err().contains("longer than"));
+    }
+
+    #[test]
+    fn cards_alone_get_a_fallback_text() {
+        let mut card = notification();
+        card.text = None;
+        card.card = Some(AdaptiveCard::new());
+        let message = card.into_message().unwrap();
+        assert_eq!(message.text.as_deref(), Some("Adaptive card"));
+        let attachments = message.attachments.unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].content_type, Notification::CARD_CONTENT_TYPE);
+
+        let mut described = notification();
+        described.text = None;
+        described.card = Some(AdaptiveCard {
+            fallback_text: Some("Build #42 failed".to_string()),
+            ..AdaptiveCard::new()
+        });
+        let message = described.into_message().unwrap();
+        assert_eq!(message.text.as_deref(), Some("Build #42 failed"));
+
+        // Text sent along with the card is kept as it is.
+        let mut both = notification();
+        both.card = Some(AdaptiveCard::new());
+        let message = both.into_message().unwrap();
+        assert_eq!(message.text.as_deref(), Some("Deploy finished."));
+    }
+}
@@ -1,9 +1,12 @@
 // std.
-use std::io::{Error, ErrorKind};
+use std::fmt;
 
 // Logging.
 use log::{debug, error};
 
+// Regex.
+use regex::Regex;
+
 // Own.
 use crate::types::{ArgTuple, Argument, Callback};
 
@@ -19,28 +22,62 @@ pub struct Command<'a> {
 }
 
 impl<'a> Command<'a> {
-    const INVALID_CMD: &'static str = "You have entered an invalid Command!";
-    const NO_CMD: &'static str = "Command was not specified!";
-    // const INVALID_SYNTAX: &str = "Sorry, I could not understand you.";
-    const MISSING_ARGS: &'static str = "Missing required variables.";
+    // Name reported for commands resolved through the default handler.
+    pub const DEFAULT: &'static str = "default";
+}
+
+// ###################################################################
+// Reasons a message could not be parsed into a command.
+// ###################################################################
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    NoCommand,        // The message only holds the bot name.
+    InvalidCommand,   // No command, pattern nor default handler matched.
+    MissingArguments, // A known command without all its required arguments.
+    TooManyArguments, // A known command with more arguments than declared.
+}
 
-    pub fn invalid(error: &str) -> Error {
-        Error::new(ErrorKind::InvalidData, format!("{}", error))
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NoCommand => write!(f, "Command was not specified!"),
+            ParseError::InvalidCommand => write!(f, "You have entered an invalid Command!"),
+            ParseError::MissingArguments => write!(f, "Missing required variables."),
+            ParseError::TooManyArguments => write!(f, "Too many arguments."),
+        }
     }
 }
 
+impl std::error::Error for ParseError {}
+
+// ###################################################################
+// Natural language pattern route.
+// ###################################################################
+
+struct Pattern {
+    name: String, // Reported as the command, defaults to the regex.
+    regex: Regex,
+    priority: i32,
+    callback: Callback,
+}
+
 // ###################################################################
 // Define the Parser struct
 // ###################################################################
 
 pub(crate) struct Parser {
     commands: std::collections::HashMap<String, (Callback, Vec<Box<dyn Argument>>)>,
+    patterns: Vec<Pattern>,
+    default: Option<Callback>,
 }
 
-impl<'a> Parser {
+impl Parser {
     pub fn new() -> Self {
         Parser {
             commands: std::collections::HashMap::new(),
+            patterns: Vec::new(),
+            default: None,
         }
     }
 
@@ -56,11 +93,55 @@ impl<'a> Parser {
      * Args: The vector of required/optional args that conform that specific command.
      * Callback: The custom user defined function that contains the command implementation.
      */
-
     pub fn add_command(&mut self, command: &str, args: Vec<Box<dyn Argument>>, callback: Callback) {
         self.commands.insert(command.to_string(), (callback, args));
     }
 
+    // ------------------------------------------------------------------------------
+    // Append a regex route, evaluated after the strict commands.
+    // ------------------------------------------------------------------------------
+
+    /**
+     * Patterns are tried from the highest to the lowest priority. Patterns sharing
+     * the same priority keep their registration order.
+     */
+    pub fn add_pattern(&mut self, regex: Regex, priority: i32, callback: Callback) {
+        let name = regex.as_str().to_string();
+        self.add_named_pattern(&name, regex, priority, callback);
+    }
+
+    pub fn add_named_pattern(
+        &mut self,
+        name: &str,
+        regex: Regex,
+        priority: i32,
+        callback: Callback,
+    ) {
+        let position = self
+            .patterns
+            .iter()
+            .position(|pattern| pattern.priority < priority)
+            .unwrap_or(self.patterns.len());
+
+        self.patterns.insert(
+            position,
+            Pattern {
+                name: name.to_string(),
+                regex,
+                priority,
+                callback,
+            },
+        );
+    }
+
+    // ------------------------------------------------------------------------------
+    // Set the handler for messages that match no command nor pattern.
+    // ------------------------------------------------------------------------------
+
+    pub fn set_default(&mut self, callback: Callback) {
+        self.default = Some(callback);
+    }
+
     // ------------------------------------------------------------------------------
     // Retrieve the set of arguments required for proper command execution.
     // ------------------------------------------------------------------------------
@@ -70,69 +151,291 @@ impl<'a> Parser {
     // }
 
     // ------------------------------------------------------------------------------
-    // Parse the plain text string values into a usable command.
+    // Parse the plain text string values into a usable command. Patterns are
+    // matched against the text following the leading bot mention, if any.
+    // ------------------------------------------------------------------------------
+
+    pub fn parse(
+        &self,
+        plain_string_message: String,
+        mention: Option<&str>,
+    ) -> Result<Command<'_>, ParseError> {
+        // Strict commands always take precedence. A known command with missing or
+        // extra arguments is reported instead of being handed over to the patterns.
+        let strict_error = match self.parse_command(&plain_string_message) {
+            Ok(command) => return Ok(command),
+            Err(e @ (ParseError::MissingArguments | ParseError::TooManyArguments)) => {
+                return Err(e)
+            }
+            Err(e) => e,
+        };
+
+        let addressed = mention
+            .and_then(|mention| plain_string_message.trim_start().strip_prefix(mention))
+            .unwrap_or(&plain_string_message);
+        if let Some(command) = self.parse_pattern(addressed) {
+            return Ok(command);
+        }
+
+        match &self.default {
+            Some(callback) => {
+                debug!("No command nor pattern matched, using the default handler.");
+                Ok(Command {
+                    command: Command::DEFAULT.to_string(),
+                    required_arguments: Vec::new(),
+                    optional_arguments: Vec::new(),
+                    callback,
+                })
+            }
+            None => Err(strict_error),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Match the message against the registered strict commands.
     // ------------------------------------------------------------------------------
 
-    pub fn parse(&self, plain_string_message: String) -> Result<Command, Error> {
+    fn parse_command(&self, plain_string_message: &str) -> Result<Command<'_>, ParseError> {
         // Separate the bot name from the actual command and arguments. \/?\w+
         let parts = plain_string_message.split(' ').collect::<Vec<&str>>();
         let num_parts = parts.len();
         if num_parts <= 1 {
             error!("No command has been specified!");
-            return Err::<Command, Error>(Command::invalid(Command::NO_CMD));
+            return Err(ParseError::NoCommand);
         }
 
         // If the command is correctly initialized, check if it is available as
         // a key within the hasmap.
-        if !self.commands.contains_key(parts[1]) {
+        let Some((callback, arguments)) = self.commands.get(parts[1]) else {
             error!("Command not found!");
-            return Err::<Command, Error>(Command::invalid(Command::INVALID_CMD));
-        }
+            return Err(ParseError::InvalidCommand);
+        };
+        let values = &parts[2..];
 
-        // If the commands is present within the registered commands, retrive the
-        // structure information.
-        let command_structure = self.commands.get(parts[1]).unwrap();
-        let arguments_len = command_structure.1.len();
+        // Arguments are positional: every declared argument past the given values
+        // has to be optional.
+        if values.len() > arguments.len() {
+            error!("Too many arguments given to the command {}!", parts[1]);
+            return Err(ParseError::TooManyArguments);
+        }
+        if arguments[values.len()..]
+            .iter()
+            .any(|argument| argument.is_required())
+        {
+            error!("Did not specified all the required arguments to execute this command!");
+            return Err(ParseError::MissingArguments);
+        }
 
         let mut required_arguments = Vec::<(String, String)>::new();
         let mut optional_arguments = Vec::<(String, String)>::new();
-
-        // Check if the required arguments list is satisfied.
-        if arguments_len >= num_parts - 2 {
-            for index in 0..num_parts - 2 {
-                if command_structure.1[index].is_required() {
-                    debug!("Required command: {}", command_structure.1[index].name());
-                    required_arguments.push((
-                        command_structure.1[index].name().to_string(),
-                        parts[index].to_string(),
-                    ));
-                } else {
-                    debug!("Optional command: {}", command_structure.1[index].name());
-                    optional_arguments.push((
-                        command_structure.1[index].name().to_string(),
-                        parts[index].to_string(),
-                    ));
-                }
+        for (argument, value) in arguments.iter().zip(values) {
+            if argument.is_required() {
+                debug!("Required command: {}", argument.name());
+                required_arguments.push((argument.name().to_string(), value.to_string()));
+            } else {
+                debug!("Optional command: {}", argument.name());
+                optional_arguments.push((argument.name().to_string(), value.to_string()));
             }
-        } else {
-            error!("Did not specified all the required arguments to execute this command!");
-            return Err::<Command, Error>(Command::invalid(Command::MISSING_ARGS));
         }
 
-        debug!("Calling the callback function supplied on the argument list.");
-        // Execute the callback function.
-        // command_structure.0(&required_arguments, &optional_arguments);
-
         // Return the final parsed command with its respective required/optional
         // arguments classified.
 
         let command = Command {
             command: parts[1].to_string(),
-            optional_arguments: optional_arguments,
-            required_arguments: required_arguments,
-            callback: &command_structure.0,
+            optional_arguments,
+            required_arguments,
+            callback,
         };
 
         Ok(command)
     }
+
+    // ------------------------------------------------------------------------------
+    // Match the message against the registered patterns, by priority.
+    // ------------------------------------------------------------------------------
+
+    fn parse_pattern(&self, plain_string_message: &str) -> Option<Command<'_>> {
+        self.patterns.iter().find_map(|pattern| {
+            let captures = pattern.regex.captures(plain_string_message)?;
+            debug!("Message matched the pattern: {}", pattern.name);
+
+            // Every named group that took part in the match is handed over as a
+            // required argument.
+            let required_arguments = pattern
+                .regex
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|value| (name.to_string(), value.as_str().to_string()))
+                })
+                .collect::<ArgTuple>();
+
+            Some(Command {
+                command: pattern.name.clone(),
+                required_arguments,
+                optional_arguments: Vec::new(),
+                callback: &pattern.callback,
+            })
+        })
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HandlerResult, Message, OptionalArgument, RequiredArgument};
+    use crate::WebexClient;
+    use std::future::Future;
+    use std::pin::Pin;
+
+    fn noop(
+        _client: WebexClient,
+        _message: Message,
+        _required_arguments: ArgTuple,
+        _optional_arguments: ArgTuple,
+    ) -> Pin<Box<dyn Future<Output = HandlerResult> + Send + Sync + 'static>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn strict_arguments_skip_the_bot_name_and_command() {
+        let mut parser = Parser::new();
+        parser.add_command(
+            "deploy",
+            vec![
+                Box::new(RequiredArgument::<String>::new("service")),
+                Box::new(OptionalArgument::<String>::new("env")),
+            ],
+            noop,
+        );
+
+        let command = parser
+            .parse("Bot deploy api prod".to_string(), None)
+            .unwrap();
+        assert_eq!(command.command, "deploy");
+        assert_eq!(command.required_arguments, vec![pair("service", "api")]);
+        assert_eq!(command.optional_arguments, vec![pair("env", "prod")]);
+    }
+
+    #[test]
+    fn strict_arguments_are_checked() {
+        let mut parser = Parser::new();
+        parser.add_command(
+            "deploy",
+            vec![
+                Box::new(RequiredArgument::<String>::new("service")),
+                Box::new(OptionalArgument::<String>::new("env")),
+            ],
+            noop,
+        );
+        parser.add_pattern(Regex::new("deploy").unwrap(), 0, noop);
+
+        // Optional arguments can be left out.
+        let command = parser.parse("Bot deploy api".to_string(), None).unwrap();
+        assert_eq!(command.required_arguments, vec![pair("service", "api")]);
+        assert!(command.optional_arguments.is_empty());
+
+        // Wrong arguments are reported rather than handed over to the patterns.
+        let error = parser.parse("Bot deploy".to_string(), None).err();
+        assert_eq!(error, Some(ParseError::MissingArguments));
+        let error = parser
+            .parse("Bot deploy api prod eu".to_string(), None)
+            .err();
+        assert_eq!(error, Some(ParseError::TooManyArguments));
+
+        let error = parser.parse("Bot".to_string(), None).err();
+        assert_eq!(error, Some(ParseError::NoCommand));
+        let error = parser.parse("Bot status".to_string(), None).err();
+        assert_eq!(error, Some(ParseError::InvalidCommand));
+    }
+
+    #[test]
+    fn higher_priority_patterns_go_first() {
+        let mut parser = Parser::new();
+        parser.add_pattern(Regex::new("low").unwrap(), 0, noop);
+        parser.add_pattern(Regex::new("high").unwrap(), 10, noop);
+        parser.add_pattern(Regex::new("also low").unwrap(), 0, noop);
+
+        let command = parser.parse("high and also low".to_string(), None).unwrap();
+        assert_eq!(command.command, "high");
+
+        // Same priority: registration order.
+        let command = parser.parse("also low".to_string(), None).unwrap();
+        assert_eq!(command.command, "low");
+    }
+
+    #[test]
+    fn named_captures_are_required_arguments() {
+        let mut parser = Parser::new();
+        parser.add_pattern(
+            Regex::new(r"weather in (?P<city>\w+)(?: on (?P<day>\w+))?").unwrap(),
+            0,
+            noop,
+        );
+
+        let command = parser
+            .parse("what's the weather in Paris".to_string(), None)
+            .unwrap();
+        assert_eq!(command.required_arguments, vec![pair("city", "Paris")]);
+
+        let command = parser
+            .parse("weather in Oslo on monday".to_string(), None)
+            .unwrap();
+        assert_eq!(
+            command.required_arguments,
+            vec![pair("city", "Oslo"), pair("day", "monday")]
+        );
+    }
+
+    #[test]
+    fn strict_commands_win_over_patterns_and_default() {
+        let mut parser = Parser::new();
+        parser.add_command("help", Vec::new(), noop);
+        parser.add_pattern(Regex::new("help").unwrap(), 100, noop);
+        parser.set_default(noop);
+
+        assert_eq!(
+            parser.parse("Bot help".to_string(), None).unwrap().command,
+            "help"
+        );
+        assert_eq!(
+            parser
+                .parse("Bot nothing".to_string(), None)
+                .unwrap()
+                .command,
+            Command::DEFAULT
+        );
+    }
+
+    #[test]
+    fn unmatched_messages_fail_without_default() {
+        let parser = Parser::new();
+        assert!(parser.parse("Bot unknown".to_string(), None).is_err());
+    }
+
+    #[test]
+    fn anchored_patterns_match_after_the_mention() {
+        let mut parser = Parser::new();
+        parser.add_named_pattern("remind", Regex::new(r"^\s*remind\b").unwrap(), 0, noop);
+
+        let command = parser
+            .parse("Futbolito Bot remind me".to_string(), Some("Futbolito Bot"))
+            .unwrap();
+        assert_eq!(command.command, "remind");
+        assert!(parser.parse("remind me".to_string(), None).is_ok());
+        assert!(parser
+            .parse("Bot please remind me".to_string(), Some("Bot"))
+            .is_err());
+    }
 }
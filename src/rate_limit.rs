@@ -0,0 +1,415 @@
+// std.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// logging.
+use log::warn;
+
+// Own.
+use crate::events::Event;
+use crate::metrics::Metrics;
+use crate::middleware::{BoxFuture, Middleware, Next, Outcome, Request};
+use crate::types::{ActivityType, MessageOut};
+
+// ###################################################################
+// Token bucket limit.
+// ###################################################################
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub capacity: u32,    // Maximum burst of invocations.
+    pub period: Duration, // Time needed to refill the whole bucket.
+}
+
+impl Limit {
+    // Shortest refill period, a zero period would never refill.
+    const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        Limit {
+            capacity,
+            period: period.max(Self::MIN_PERIOD),
+        }
+    }
+
+    pub fn per_second(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(60))
+    }
+
+    pub fn per_hour(capacity: u32) -> Self {
+        Self::new(capacity, Duration::from_secs(3600))
+    }
+}
+
+// ###################################################################
+// Limiter state, shareable between servers or replicas.
+// ###################################################################
+
+pub trait RateLimiter: Send + Sync {
+    // Take a token from every bucket, only when all of them have one left.
+    // Otherwise nothing is taken and the index of the first exhausted bucket
+    // is returned.
+    fn try_acquire_all(&self, buckets: &[(String, Limit)]) -> Option<usize>;
+
+    // Take a token from the bucket identified by the key. False when throttled.
+    fn try_acquire(&self, key: &str, limit: &Limit) -> bool {
+        self.try_acquire_all(&[(key.to_string(), *limit)]).is_none()
+    }
+}
+
+// ###################################################################
+// In-memory token buckets.
+// ###################################################################
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    period: Duration,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, limit: &Limit) {
+        let capacity = f64::from(limit.capacity);
+        let period = limit.period.max(Limit::MIN_PERIOD);
+        let refill =
+            now.duration_since(self.last_refill).as_secs_f64() / period.as_secs_f64() * capacity;
+        self.tokens = (self.tokens + refill).min(capacity);
+        self.last_refill = now;
+        self.period = period;
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant,
+}
+
+pub struct InMemoryRateLimiter {
+    state: Mutex<Buckets>,
+}
+
+impl InMemoryRateLimiter {
+    // How often the buckets that are full again are forgotten.
+    const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new() -> Self {
+        InMemoryRateLimiter {
+            state: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl Default for InMemoryRateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter for InMemoryRateLimiter {
+    fn try_acquire_all(&self, requested: &[(String, Limit)]) -> Option<usize> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // Forget the buckets that are full again, so the map does not grow forever.
+        if now.duration_since(state.last_sweep) >= Self::SWEEP_INTERVAL {
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.last_refill) < bucket.period);
+            state.last_sweep = now;
+        }
+
+        // Check every bucket before taking anything.
+        for (index, (key, limit)) in requested.iter().enumerate() {
+            let bucket = state.buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: f64::from(limit.capacity),
+                last_refill: now,
+                period: limit.period,
+            });
+            bucket.refill(now, limit);
+            if bucket.tokens < 1.0 {
+                return Some(index);
+            }
+        }
+
+        for (key, _) in requested {
+            if let Some(bucket) = state.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        None
+    }
+}
+
+// ###################################################################
+// Middleware throttling the command invocations.
+// ###################################################################
+
+/**
+ * The person and room limits are checked when a message or card action
+ * leaves the worker queue, before its details are retrieved from Webex,
+ * so a throttled call costs no API request. Command limits need the parsed
+ * command and are checked in the chain. The reply to a throttled person is
+ * itself sent at most once per period of the exhausted limit.
+ */
+pub struct RateLimit {
+    limiter: Arc<dyn RateLimiter>,
+    per_person: Option<Limit>,
+    per_room: Option<Limit>,
+    per_command: HashMap<String, Limit>,
+    reply: Option<String>,
+}
+
+impl RateLimit {
+    pub fn new() -> Self {
+        Self::with_limiter(Arc::new(InMemoryRateLimiter::new()))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Use a custom (possibly shared) limiter state.
+    // ------------------------------------------------------------------------------
+
+    pub fn with_limiter(limiter: Arc<dyn RateLimiter>) -> Self {
+        RateLimit {
+            limiter,
+            per_person: None,
+            per_room: None,
+            per_command: HashMap::new(),
+            reply: None,
+        }
+    }
+
+    // Limit applied to every person across all the commands.
+    pub fn per_person(mut self, limit: Limit) -> Self {
+        self.per_person = Some(limit);
+        self
+    }
+
+    // Limit applied to every room across all the commands.
+    pub fn per_room(mut self, limit: Limit) -> Self {
+        self.per_room = Some(limit);
+        self
+    }
+
+    // Limit applied to every person for a single command.
+    pub fn per_command(mut self, command: &str, limit: Limit) -> Self {
+        self.per_command.insert(command.to_string(), limit);
+        self
+    }
+
+    // Reply sent when an invocation is throttled. Silently dropped otherwise.
+    pub fn reply(mut self, reply: &str) -> Self {
+        self.reply = Some(reply.to_string());
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Take a token from every given bucket, or from none of them when one is
+    // exhausted. Returns the scope and limit of the first exhausted one.
+    // ------------------------------------------------------------------------------
+
+    fn throttled_scope(
+        &self,
+        checks: &[Option<(&'static str, String, Limit)>],
+    ) -> Option<(&'static str, Limit)> {
+        let checks: Vec<_> = checks.iter().flatten().collect();
+        let buckets: Vec<(String, Limit)> = checks
+            .iter()
+            .map(|(_, key, limit)| (key.clone(), *limit))
+            .collect();
+
+        self.limiter
+            .try_acquire_all(&buckets)
+            .map(|index| (checks[index].0, checks[index].2))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Outcome of a throttled call: the reply, unless the person already got it
+    // within the period of the exhausted limit.
+    // ------------------------------------------------------------------------------
+
+    fn throttled(&self, room_id: Option<&str>, person: &str, limit: Limit) -> Outcome {
+        let Some(reply) = &self.reply else {
+            return Outcome::Dropped;
+        };
+        let once = Limit::new(1, limit.period);
+        if !self
+            .limiter
+            .try_acquire(&format!("reply:{}", person), &once)
+        {
+            return Outcome::Dropped;
+        }
+        Outcome::Reply(MessageOut {
+            room_id: room_id.map(str::to_string),
+            text: Some(reply.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Outcome> {
+        Box::pin(async move {
+            let person = request.message.person_id.as_deref().unwrap_or_default();
+            let check = self.per_command.get(&request.command).map(|limit| {
+                (
+                    "command",
+                    format!("command:{}:{}", request.command, person),
+                    *limit,
+                )
+            });
+            let (scope, limit) = match self.throttled_scope(&[check]) {
+                Some(throttled) => throttled,
+                None => return next.run(request).await,
+            };
+
+            Metrics::get_instance().record_rate_limited(scope);
+            warn!(
+                "[Rate limited]: {} running {} in room {} ({} limit)",
+                request.message.person_email.as_deref().unwrap_or_default(),
+                request.command,
+                request.message.room_id.as_deref().unwrap_or_default(),
+                scope
+            );
+            self.throttled(request.message.room_id.as_deref(), person, limit)
+        })
+    }
+
+    fn screen(&self, event: &Event) -> Option<Outcome> {
+        let invocation =
+            event.is_new_message() || event.activity_type == ActivityType::AdaptiveCardSubmit;
+        if !invocation {
+            return None;
+        }
+
+        let actor = event.actor.as_ref();
+        let person = actor.map(|actor| actor.id.as_str()).unwrap_or_default();
+        let room = event.room_id.as_deref().unwrap_or_default();
+        let checks = [
+            self.per_person
+                .map(|limit| ("person", format!("person:{}", person), limit)),
+            self.per_room
+                .map(|limit| ("room", format!("room:{}", room), limit)),
+        ];
+        let (scope, limit) = self.throttled_scope(&checks)?;
+
+        Metrics::get_instance().record_rate_limited(scope);
+        warn!(
+            "[Rate limited]: {} in room {} ({} limit)",
+            actor
+                .and_then(|actor| actor.email_address.as_deref())
+                .unwrap_or(person),
+            room,
+            scope
+        );
+        Some(self.throttled(event.room_id.as_deref(), person, limit))
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Actor, MessageActivity};
+
+    fn message(person: &str, room: &str) -> Event {
+        Event {
+            activity_type: ActivityType::Message(MessageActivity::Posted),
+            id: "message".to_string(),
+            room_id: Some(room.to_string()),
+            actor: Some(Actor {
+                id: person.to_string(),
+                ..Default::default()
+            }),
+            created: None,
+            data: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn messages_are_screened_against_the_person_and_room_limits() {
+        let limit = RateLimit::new()
+            .per_person(Limit::per_hour(1))
+            .per_room(Limit::per_hour(2));
+
+        assert!(limit.screen(&message("alice", "room")).is_none());
+        assert!(matches!(
+            limit.screen(&message("alice", "room")),
+            Some(Outcome::Dropped)
+        ));
+        assert!(limit.screen(&message("bob", "room")).is_none());
+        // The room is exhausted now, whoever writes.
+        assert!(limit.screen(&message("carol", "room")).is_some());
+        assert!(limit.screen(&message("carol", "other")).is_none());
+
+        // Other events never consume tokens.
+        let mut deleted = message("alice", "other");
+        deleted.activity_type = ActivityType::Message(MessageActivity::Deleted);
+        assert!(limit.screen(&deleted).is_none());
+    }
+
+    #[test]
+    fn throttled_people_are_told_once_per_period() {
+        let limit = RateLimit::new()
+            .per_person(Limit::per_hour(1))
+            .reply("Slow down!");
+
+        assert!(limit.screen(&message("alice", "room")).is_none());
+        match limit.screen(&message("alice", "room")) {
+            Some(Outcome::Reply(reply)) => {
+                assert_eq!(reply.room_id.as_deref(), Some("room"));
+                assert_eq!(reply.text.as_deref(), Some("Slow down!"));
+            }
+            other => panic!("expected a reply, got {:?}", other),
+        }
+        assert!(matches!(
+            limit.screen(&message("alice", "room")),
+            Some(Outcome::Dropped)
+        ));
+    }
+
+    #[test]
+    fn rejected_calls_do_not_drain_the_other_buckets() {
+        let limiter = InMemoryRateLimiter::new();
+        let person = ("person".to_string(), Limit::per_hour(2));
+        let room = ("room".to_string(), Limit::per_hour(1));
+
+        assert_eq!(
+            limiter.try_acquire_all(&[person.clone(), room.clone()]),
+            None
+        );
+        assert_eq!(limiter.try_acquire_all(&[person.clone(), room]), Some(1));
+        // The throttled call left the person token in place.
+        assert!(limiter.try_acquire("person", &person.1));
+        assert!(!limiter.try_acquire("person", &person.1));
+    }
+
+    #[test]
+    fn zero_periods_are_clamped() {
+        let limit = Limit::new(1, Duration::ZERO);
+        assert!(!limit.period.is_zero());
+
+        let limiter = InMemoryRateLimiter::new();
+        let raw = Limit {
+            capacity: 1,
+            period: Duration::ZERO,
+        };
+        assert!(limiter.try_acquire("key", &raw));
+    }
+}
@@ -0,0 +1,328 @@
+// std.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Tokio.
+use rocket::tokio;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+// Future.
+use futures_util::{SinkExt, StreamExt};
+
+// logging.
+use log::{info, warn};
+
+// Own.
+use crate::shutdown::Shutdown;
+
+pub(crate) type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+pub(crate) type Connect =
+    Box<dyn FnMut(bool) -> Pin<Box<dyn Future<Output = Result<WsStream, String>> + Send>> + Send>;
+
+// ###################################################################
+// Connection state reported to the state change callbacks.
+// ###################################################################
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected(String), // Why the connection was lost or couldn't be opened.
+    Reconnecting { attempt: u32, delay: Duration },
+    Closed, // Stopped for good: shutdown, consumer gone or out of attempts.
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "connecting"),
+            ConnectionState::Connected => write!(f, "connected"),
+            ConnectionState::Disconnected(reason) => write!(f, "disconnected ({})", reason),
+            ConnectionState::Reconnecting { attempt, delay } => {
+                write!(f, "reconnecting in {:?} (attempt {})", delay, attempt)
+            }
+            ConnectionState::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+pub type StateCallback = Arc<dyn Fn(&ConnectionState) + Send + Sync>;
+
+// ###################################################################
+// Reconnection and keepalive settings.
+// ###################################################################
+
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,   // Delay before the first reconnection attempt.
+    max_delay: Duration,       // Cap of the exponential backoff.
+    multiplier: f64,           // Backoff growth between attempts.
+    max_attempts: Option<u32>, // Consecutive failures before giving up, none retries forever.
+    ping_interval: Duration,   // Ping the peer after this long without traffic.
+    pong_timeout: Duration,    // Silence tolerated after a ping before the connection is dropped.
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            max_attempts: None,
+            ping_interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    const MIN_KEEPALIVE: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn backoff(
+        mut self,
+        initial_delay: Duration,
+        max_delay: Duration,
+        multiplier: f64,
+    ) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Both durations are clamped to at least one second.
+    // ------------------------------------------------------------------------------
+
+    pub fn keepalive(mut self, ping_interval: Duration, pong_timeout: Duration) -> Self {
+        self.ping_interval = ping_interval.max(Self::MIN_KEEPALIVE);
+        self.pong_timeout = pong_timeout.max(Self::MIN_KEEPALIVE);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Delay before the given attempt, with up to 10% jitter so that replicas
+    // don't reconnect in lockstep.
+    // ------------------------------------------------------------------------------
+
+    fn delay(&self, attempt: u32) -> Duration {
+        // In f64, as the backoff overflows a Duration long before the attempts
+        // run out.
+        let exponent = attempt.saturating_sub(1).min(32) as i32;
+        let max_delay = self.max_delay.as_secs_f64();
+        let delay =
+            (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent)).min(max_delay);
+        // The jitter only has to differ between replicas, not to be unpredictable:
+        // the sub-second part of the clock is enough and spares a rand dependency.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        let jitter = (nanos % 1000) as f64 / 10_000.0;
+        Duration::try_from_secs_f64(delay * (1.0 + jitter)).unwrap_or(self.max_delay)
+    }
+}
+
+// ###################################################################
+// Keep a connection up: connect, pump the frames both ways, and
+// reconnect with backoff when it drops.
+// ###################################################################
+
+/**
+ * `connect` opens (and authorizes) a new connection. Its argument is true
+ * after a failed attempt, asking it to register again rather than reuse a
+ * possibly stale URL. Incoming text and binary frames go to `inbound`,
+ * frames from `outbound` are written to the socket. The loop ends on
+ * shutdown, when the inbound consumer is gone or out of attempts.
+ */
+pub(crate) async fn supervise(
+    mut connect: Connect,
+    policy: ReconnectPolicy,
+    on_state: Option<StateCallback>,
+    shutdown: Option<Shutdown>,
+    inbound: Sender<Message>,
+    mut outbound: Option<Receiver<Message>>,
+) {
+    let notify = |state: ConnectionState| {
+        match &state {
+            ConnectionState::Disconnected(_) => warn!("Websocket {}", state),
+            _ => info!("Websocket {}", state),
+        }
+        if let Some(callback) = &on_state {
+            callback(&state);
+        }
+    };
+
+    let mut attempt = 0;
+    let mut register = false;
+    loop {
+        notify(ConnectionState::Connecting);
+        match connect(register).await {
+            Ok(stream) => {
+                attempt = 0;
+                register = false;
+                notify(ConnectionState::Connected);
+                match pump(stream, &policy, &shutdown, &inbound, &mut outbound).await {
+                    PumpEnd::Closed => break,
+                    PumpEnd::Lost(reason) => notify(ConnectionState::Disconnected(reason)),
+                }
+            }
+            Err(reason) => {
+                register = true;
+                notify(ConnectionState::Disconnected(reason));
+            }
+        }
+
+        attempt += 1;
+        if inbound.is_closed() || policy.max_attempts.is_some_and(|max| attempt > max) {
+            break;
+        }
+
+        let delay = policy.delay(attempt);
+        notify(ConnectionState::Reconnecting { attempt, delay });
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = wait_for_shutdown(&shutdown) => break,
+        }
+    }
+
+    notify(ConnectionState::Closed);
+}
+
+enum PumpEnd {
+    Closed,       // Stop for good.
+    Lost(String), // Reconnect.
+}
+
+async fn pump(
+    mut stream: WsStream,
+    policy: &ReconnectPolicy,
+    shutdown: &Option<Shutdown>,
+    inbound: &Sender<Message>,
+    outbound: &mut Option<Receiver<Message>>,
+) -> PumpEnd {
+    let mut ticker = tokio::time::interval(policy.ping_interval.min(policy.pong_timeout));
+    ticker.tick().await;
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            frame = stream.next() => {
+                last_seen = Instant::now();
+                match frame {
+                    Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+                        if inbound.send(frame).await.is_err() {
+                            let _ = stream.close(None).await;
+                            return PumpEnd::Closed;
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => {
+                        return PumpEnd::Lost(format!("closed by the peer: {:?}", frame));
+                    }
+                    // Pings are answered by tungstenite, pongs only refresh `last_seen`.
+                    Some(Ok(_)) => (),
+                    Some(Err(e)) => return PumpEnd::Lost(e.to_string()),
+                    None => return PumpEnd::Lost("stream ended".to_string()),
+                }
+            }
+            frame = next_outbound(outbound) => match frame {
+                Some(frame) => {
+                    if let Err(e) = stream.send(frame).await {
+                        return PumpEnd::Lost(e.to_string());
+                    }
+                }
+                None => *outbound = None,
+            },
+            _ = ticker.tick() => {
+                // No traffic, not even a pong: the connection is half-open.
+                if last_seen.elapsed() > policy.ping_interval + policy.pong_timeout {
+                    return PumpEnd::Lost("keepalive timeout".to_string());
+                }
+                if last_seen.elapsed() >= policy.ping_interval {
+                    if let Err(e) = stream.send(Message::Ping(Vec::new())).await {
+                        return PumpEnd::Lost(e.to_string());
+                    }
+                }
+            }
+            _ = wait_for_shutdown(shutdown) => {
+                let _ = stream.close(None).await;
+                return PumpEnd::Closed;
+            }
+        }
+    }
+}
+
+async fn next_outbound(outbound: &mut Option<Receiver<Message>>) -> Option<Message> {
+    match outbound {
+        Some(outbound) => outbound.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Resolve when the shutdown begins, never without a shutdown handle.
+// ----------------------------------------------------------------------------
+
+pub(crate) async fn wait_for_shutdown(shutdown: &Option<Shutdown>) {
+    match shutdown {
+        Some(shutdown) => shutdown.closed().await,
+        None => std::future::pending().await,
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_keepalive_durations_are_clamped() {
+        let policy = ReconnectPolicy::new().keepalive(Duration::ZERO, Duration::ZERO);
+        assert_eq!(policy.ping_interval, Duration::from_secs(1));
+        assert_eq!(policy.pong_timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_cap_with_bounded_jitter() {
+        let policy =
+            ReconnectPolicy::new().backoff(Duration::from_secs(1), Duration::from_secs(8), 2.0);
+        for (attempt, base) in [(1, 1), (2, 2), (3, 4), (4, 8), (10, 8)] {
+            let delay = policy.delay(attempt);
+            let base = Duration::from_secs(base);
+            assert!(delay >= base && delay <= base.mul_f64(1.1), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn large_backoffs_stay_at_the_cap() {
+        let cap = Duration::from_secs(60);
+        for multiplier in [4.0, 10.0, 1e300, f64::INFINITY] {
+            let policy = ReconnectPolicy::new().backoff(Duration::from_secs(1), cap, multiplier);
+            for attempt in [21, 33, 1_000, u32::MAX] {
+                let delay = policy.delay(attempt);
+                assert!(delay >= cap && delay <= cap.mul_f64(1.1), "{:?}", delay);
+            }
+        }
+
+        let policy = ReconnectPolicy::new().backoff(Duration::from_secs(1), Duration::MAX, 10.0);
+        assert_eq!(policy.delay(u32::MAX), Duration::MAX);
+    }
+}
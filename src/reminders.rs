@@ -0,0 +1,485 @@
+// std.
+use std::time::Duration;
+
+// chrono.
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// serde.
+use serde::{Deserialize, Serialize};
+
+// Regex.
+use regex::Regex;
+
+// logging.
+use log::error;
+
+// Own.
+use crate::scheduler::{Schedule, Scheduler, Trigger};
+use crate::types::{ArgTuple, HandlerResult, Message, MessageOut};
+use crate::WebexClient;
+
+// ###################################################################
+// Built-in reminders, opt-in through `WebexBotServer::enable_reminders`.
+// ###################################################################
+
+pub const COMMAND: &str = "remind";
+pub(crate) const JOB: &str = "reminders.fire";
+
+// Priority of the reminders pattern, ahead of the user defined patterns.
+pub(crate) const PRIORITY: i32 = 1000;
+
+const USAGE: &str = "Usage:\n\
+    - `remind me in 2h to check the deploy`\n\
+    - `remind #room tomorrow 9am standup notes`\n\
+    - `remind me at 17:30 to leave`\n\
+    - `remind list`\n\
+    - `remind cancel <number>`";
+
+// Anchored at the start of the text, which follows the bot mention in group spaces.
+pub(crate) fn pattern() -> Regex {
+    Regex::new(r"(?i)^\s*/?remind(?P<request>(?:\s.*)?)$").unwrap()
+}
+
+// ###################################################################
+// Settings of the reminders, kept with their job in the scheduler.
+// ###################################################################
+
+pub(crate) struct Config {
+    pub timezone: Tz, // Zone of the times without an explicit date or delay.
+}
+
+impl Config {
+    pub fn apply(&self, scheduler: &Scheduler) {
+        scheduler.set_job_config(JOB, serde_json::json!({ "timezone": self.timezone.name() }));
+    }
+
+    fn of(scheduler: &Scheduler) -> Config {
+        let timezone = scheduler
+            .job_config(JOB)
+            .and_then(|config| config.get("timezone")?.as_str()?.parse::<Tz>().ok())
+            .unwrap_or(Tz::UTC);
+        Config { timezone }
+    }
+}
+
+fn timezone(client: &WebexClient) -> Tz {
+    Config::of(&client.scheduler).timezone
+}
+
+// ###################################################################
+// Reminder saved as the payload of its schedule.
+// ###################################################################
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Reminder {
+    pub room_id: String,
+    pub person_id: String,
+    pub person_email: String,
+    pub parent_id: Option<String>, // Message the reminder is threaded under.
+    pub mention: bool,             // Mention the person or address the whole room.
+    pub text: String,
+    pub due: DateTime<Utc>,
+}
+
+// ###################################################################
+// Command callback.
+// ###################################################################
+
+pub(crate) fn remind(
+    client: WebexClient,
+    message: Message,
+    required_arguments: ArgTuple,
+    _optional_arguments: ArgTuple,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = HandlerResult> + Send + Sync + 'static>> {
+    Box::pin(async move {
+        let request = required_arguments
+            .iter()
+            .find(|(name, _)| name == "request")
+            .map(|(_, value)| value.trim().to_string())
+            .unwrap_or_default();
+
+        let reply = match request.split_whitespace().next() {
+            Some("list") => list(&client, &message),
+            Some("cancel") => cancel(&client, &message, &request).await,
+            _ => create(&client, &message, &request).await,
+        };
+
+        client.send_message(&reply_to(&message, &reply)).await;
+        Ok(())
+    })
+}
+
+// ###################################################################
+// Scheduled job posting the reminder.
+// ###################################################################
+
+pub(crate) fn fire(
+    client: WebexClient,
+    payload: serde_json::Value,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + 'static>> {
+    Box::pin(async move {
+        let reminder = match serde_json::from_value::<Reminder>(payload) {
+            Ok(reminder) => reminder,
+            Err(e) => {
+                error!("Invalid reminder: {}", e);
+                return;
+            }
+        };
+
+        let markdown = if reminder.mention {
+            format!(
+                "<@personEmail:{}> reminder: {}",
+                reminder.person_email, reminder.text
+            )
+        } else {
+            format!("Reminder: {}", reminder.text)
+        };
+
+        client
+            .send_message(&MessageOut {
+                room_id: Some(reminder.room_id),
+                parent_id: reminder.parent_id,
+                markdown: Some(markdown),
+                ..Default::default()
+            })
+            .await;
+    })
+}
+
+// ###################################################################
+// Subcommands.
+// ###################################################################
+
+async fn create(client: &WebexClient, message: &Message, request: &str) -> String {
+    let (mention, rest) = match request.split_once(char::is_whitespace) {
+        Some(("me", rest)) => (true, rest),
+        Some(("#room" | "here", rest)) => (false, rest),
+        _ => return USAGE.to_string(),
+    };
+
+    let (due, text) = match parse_when(rest, Utc::now(), timezone(client)) {
+        Some(parsed) => parsed,
+        None => return format!("Sorry, I could not understand when.\n\n{}", USAGE),
+    };
+    if text.is_empty() {
+        return USAGE.to_string();
+    }
+
+    let reminder = Reminder {
+        room_id: message.room_id.clone().unwrap_or_default(),
+        person_id: message.person_id.clone().unwrap_or_default(),
+        person_email: message.person_email.clone().unwrap_or_default(),
+        // Replies can't be nested, so stay in the thread of the original message.
+        parent_id: message.parent_id.clone().or(message.id.clone()),
+        mention,
+        text,
+        due,
+    };
+
+    let payload = match serde_json::to_value(&reminder) {
+        Ok(payload) => payload,
+        Err(e) => return format!("Sorry, I could not save the reminder: {}", e),
+    };
+
+    match client
+        .scheduler
+        .schedule(JOB, Trigger::at(due), payload, true)
+        .await
+    {
+        Ok(_) => format!(
+            "Ok, I will remind {} on {}.",
+            if mention { "you" } else { "this room" },
+            format_due(due, timezone(client))
+        ),
+        Err(e) => format!("Sorry, I could not save the reminder: {}", e),
+    }
+}
+
+fn list(client: &WebexClient, message: &Message) -> String {
+    let reminders = pending(client, message);
+    if reminders.is_empty() {
+        return "You have no pending reminders in this room.".to_string();
+    }
+
+    reminders
+        .iter()
+        .enumerate()
+        .map(|(index, (_, reminder))| {
+            format!(
+                "{}. {} - {}",
+                index + 1,
+                format_due(reminder.due, timezone(client)),
+                reminder.text
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+async fn cancel(client: &WebexClient, message: &Message, request: &str) -> String {
+    let reminders = pending(client, message);
+    let selected = request
+        .split_whitespace()
+        .nth(1)
+        .and_then(|number| number.parse::<usize>().ok())
+        .and_then(|number| number.checked_sub(1))
+        .and_then(|index| reminders.get(index));
+
+    match selected {
+        Some((id, reminder)) => match client.scheduler.cancel(id).await {
+            Ok(_) => format!("Cancelled the reminder: {}", reminder.text),
+            Err(e) => format!("Sorry, I could not cancel the reminder: {}", e),
+        },
+        None => "Which one? Use `remind list` to see the numbers.".to_string(),
+    }
+}
+
+// Reminders of the author of the message in its room, by due date.
+fn pending(client: &WebexClient, message: &Message) -> Vec<(String, Reminder)> {
+    let room_id = message.room_id.clone().unwrap_or_default();
+    let person_id = message.person_id.clone().unwrap_or_default();
+
+    let mut reminders = client
+        .scheduler
+        .schedules()
+        .into_iter()
+        .filter(|schedule| schedule.job == JOB)
+        .filter_map(|Schedule { id, payload, .. }| {
+            serde_json::from_value::<Reminder>(payload)
+                .ok()
+                .map(|reminder| (id, reminder))
+        })
+        .filter(|(_, reminder)| reminder.room_id == room_id && reminder.person_id == person_id)
+        .collect::<Vec<(String, Reminder)>>();
+    reminders.sort_by_key(|(_, reminder)| reminder.due);
+    reminders
+}
+
+fn reply_to(message: &Message, markdown: &str) -> MessageOut {
+    MessageOut {
+        room_id: message.room_id.clone(),
+        parent_id: message.parent_id.clone().or(message.id.clone()),
+        markdown: Some(markdown.to_string()),
+        ..Default::default()
+    }
+}
+
+fn format_due(due: DateTime<Utc>, timezone: Tz) -> String {
+    due.with_timezone(&timezone)
+        .format("%a %b %e %H:%M %Z")
+        .to_string()
+}
+
+// ###################################################################
+// Natural language time parsing.
+// ###################################################################
+
+/**
+ * Understands the following forms, followed by the reminder text (an
+ * optional leading "to" is dropped):
+ *
+ * in 2h / in 30 minutes / in 1d 4h
+ * at 9am / at 17:30 (today, or tomorrow once the time has passed)
+ * today 5pm / tomorrow 9am / tomorrow (9am by default)
+ * on 2026-10-20 / on 2026-10-20 14:00
+ */
+pub fn parse_when(
+    input: &str,
+    now: DateTime<Utc>,
+    timezone: Tz,
+) -> Option<(DateTime<Utc>, String)> {
+    let words = input.split_whitespace().collect::<Vec<&str>>();
+    let local_now = now.with_timezone(&timezone);
+    let default_time = NaiveTime::from_hms_opt(9, 0, 0)?;
+
+    let (due, consumed) = match words.first()?.to_lowercase().as_str() {
+        "in" => {
+            let mut total = Duration::ZERO;
+            let mut consumed = 1;
+            while consumed < words.len() {
+                match parse_duration(&words[consumed..]) {
+                    Some((duration, used)) => {
+                        total = total.checked_add(duration)?;
+                        consumed += used;
+                    }
+                    None => break,
+                }
+            }
+            if total.is_zero() {
+                return None;
+            }
+            let due = now.checked_add_signed(chrono::Duration::from_std(total).ok()?)?;
+            (due, consumed)
+        }
+        "at" => {
+            let time = parse_time(words.get(1)?)?;
+            let today = local_at(timezone, local_now.date_naive(), time)?;
+            let due = if today > now {
+                today
+            } else {
+                local_at(
+                    timezone,
+                    local_now.date_naive().checked_add_days(Days::new(1))?,
+                    time,
+                )?
+            };
+            (due, 2)
+        }
+        day @ ("today" | "tomorrow") => {
+            let date = if day == "today" {
+                local_now.date_naive()
+            } else {
+                local_now.date_naive().checked_add_days(Days::new(1))?
+            };
+            match words.get(1).and_then(|word| parse_time(word)) {
+                Some(time) => (local_at(timezone, date, time)?, 2),
+                None => (local_at(timezone, date, default_time)?, 1),
+            }
+        }
+        "on" => {
+            let date = NaiveDate::parse_from_str(words.get(1)?, "%Y-%m-%d").ok()?;
+            match words.get(2).and_then(|word| parse_time(word)) {
+                Some(time) => (local_at(timezone, date, time)?, 3),
+                None => (local_at(timezone, date, default_time)?, 2),
+            }
+        }
+        _ => return None,
+    };
+
+    if due <= now || local_now.year() + 10 < due.year() {
+        return None;
+    }
+
+    let mut text = &words[consumed..];
+    if text
+        .first()
+        .is_some_and(|word| word.eq_ignore_ascii_case("to"))
+    {
+        text = &text[1..];
+    }
+    Some((due, text.join(" ")))
+}
+
+// Parse "2h", "2 hours", "30m"... Returns the duration and the words used.
+fn parse_duration(words: &[&str]) -> Option<(Duration, usize)> {
+    let first = words.first()?.to_lowercase();
+    let split = first
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(first.len());
+    let (amount, unit, used) = match first.split_at(split) {
+        (amount, "") => (amount.parse::<u64>().ok()?, words.get(1)?.to_lowercase(), 2),
+        (amount, unit) => (amount.parse::<u64>().ok()?, unit.to_string(), 1),
+    };
+
+    let seconds = match unit.trim_end_matches(',') {
+        "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86400,
+        "w" | "week" | "weeks" => 604800,
+        _ => return None,
+    };
+    Some((Duration::from_secs(amount.checked_mul(seconds)?), used))
+}
+
+// Parse "9am", "9:30pm", "17:00".
+fn parse_time(word: &str) -> Option<NaiveTime> {
+    let word = word.to_lowercase();
+    let (clock, offset) = if let Some(clock) = word.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = word.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (word.as_str(), None)
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        // A bare number is only a time with am/pm, "in 2" is not "at 2".
+        None if offset.is_some() => (clock.parse::<u32>().ok()?, 0),
+        None => return None,
+    };
+
+    let hour = match offset {
+        Some(_) if hour == 0 || hour > 12 => return None,
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+fn local_at(timezone: Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|due| due.with_timezone(&Utc))
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn the_time_zone_is_kept_per_scheduler() {
+        let madrid = Scheduler::new();
+        Config {
+            timezone: chrono_tz::Europe::Madrid,
+        }
+        .apply(&madrid);
+        assert_eq!(Config::of(&madrid).timezone, chrono_tz::Europe::Madrid);
+        assert_eq!(Config::of(&Scheduler::new()).timezone, Tz::UTC);
+    }
+
+    #[test]
+    fn delays_add_up_and_drop_the_leading_to() {
+        let (due, text) = parse_when("in 1d 2h to check the deploy", now(), Tz::UTC).unwrap();
+        assert_eq!(due, now() + chrono::Duration::hours(26));
+        assert_eq!(text, "check the deploy");
+
+        let (due, _) = parse_when("in 30 minutes stretch", now(), Tz::UTC).unwrap();
+        assert_eq!(due, now() + chrono::Duration::minutes(30));
+    }
+
+    #[test]
+    fn clock_times_are_read_in_the_time_zone() {
+        let madrid = chrono_tz::Europe::Madrid;
+        // 14:00 in Madrid (UTC+2) has passed at 12:00 UTC, so it is tomorrow's.
+        let (due, text) = parse_when("at 1pm lunch", now(), madrid).unwrap();
+        assert_eq!(due, Utc.with_ymd_and_hms(2026, 10, 19, 11, 0, 0).unwrap());
+        assert_eq!(text, "lunch");
+
+        let (due, _) = parse_when("tomorrow standup", now(), madrid).unwrap();
+        assert_eq!(due, Utc.with_ymd_and_hms(2026, 10, 19, 7, 0, 0).unwrap());
+
+        let (due, _) = parse_when("on 2026-12-24 18:00 dinner", now(), madrid).unwrap();
+        assert_eq!(due, Utc.with_ymd_and_hms(2026, 12, 24, 17, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn past_far_and_overflowing_dates_are_refused() {
+        assert!(parse_when("on 2020-01-01 too late", now(), Tz::UTC).is_none());
+        assert!(parse_when("in 20 weeks 600 weeks", now(), Tz::UTC).is_none());
+        assert!(parse_when("in 999999999 w overflow", now(), Tz::UTC).is_none());
+        assert!(parse_when("in 18446744073709551615 s overflow", now(), Tz::UTC).is_none());
+        assert!(parse_when("in soon", now(), Tz::UTC).is_none());
+    }
+
+    #[test]
+    fn only_the_command_position_triggers_reminders() {
+        let pattern = pattern();
+        assert!(pattern.is_match("remind me in 2h to stretch"));
+        assert!(pattern.is_match(" /remind list"));
+        assert!(!pattern.is_match("can you remind me later"));
+        assert!(!pattern.is_match("reminders are great"));
+    }
+}
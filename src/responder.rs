@@ -0,0 +1,122 @@
+// std.
+use std::fmt::Display;
+
+// logging.
+use log::error;
+
+// uuid.
+use uuid::Uuid;
+
+// Own.
+use crate::types::{Message, MessageOut};
+
+// ###################################################################
+// Replies posted when a command can't be parsed or its handler fails.
+// ###################################################################
+
+/**
+ * Handler errors and panics are logged with the failing command and answered
+ * with a friendly message in the originating room. The optional correlation id
+ * is appended to both, so a user report can be matched with the logs.
+ */
+#[derive(Debug, Clone)]
+pub struct ErrorResponder {
+    message: Option<String>, // Reply to failed handlers, none stays silent.
+    correlation_id: bool,    // Append a correlation id to the reply and the log.
+    parse_errors: bool,      // Tell the user why the message couldn't be parsed.
+}
+
+impl Default for ErrorResponder {
+    fn default() -> Self {
+        ErrorResponder {
+            message: Some("Sorry, something went wrong while running your command.".to_string()),
+            correlation_id: true,
+            parse_errors: false,
+        }
+    }
+}
+
+impl ErrorResponder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Never reply, only log.
+    // ------------------------------------------------------------------------------
+
+    pub fn silent() -> Self {
+        ErrorResponder {
+            message: None,
+            correlation_id: false,
+            parse_errors: false,
+        }
+    }
+
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    pub fn correlation_id(mut self, enabled: bool) -> Self {
+        self.correlation_id = enabled;
+        self
+    }
+
+    pub fn parse_errors(mut self, enabled: bool) -> Self {
+        self.parse_errors = enabled;
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Log the failure of a handler and build the reply for its room, if any.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn handler_failed(
+        &self,
+        message: &Message,
+        command: &str,
+        error: &dyn Display,
+    ) -> Option<MessageOut> {
+        let correlation_id = self.correlation_id.then(|| Uuid::new_v4().to_string());
+        error!(
+            "[Command]: {} failed for {}: {}{}",
+            command,
+            message.person_email.as_deref().unwrap_or_default(),
+            error,
+            correlation_id
+                .as_deref()
+                .map(|id| format!(" (correlation id: {})", id))
+                .unwrap_or_default()
+        );
+
+        let text = match (&self.message, correlation_id) {
+            (None, _) => return None,
+            (Some(text), None) => text.clone(),
+            (Some(text), Some(id)) => format!("{} (reference: {})", text, id),
+        };
+        Some(reply(message, text))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Log a message the parser rejected and build the reply for its room, if any.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn parse_failed(
+        &self,
+        message: &Message,
+        error: &dyn Display,
+    ) -> Option<MessageOut> {
+        error!("{}", error);
+        self.parse_errors
+            .then(|| reply(message, format!("Sorry, I couldn't run that: {}", error)))
+    }
+}
+
+fn reply(message: &Message, text: String) -> MessageOut {
+    MessageOut {
+        room_id: message.room_id.clone(),
+        text: Some(text),
+        ..Default::default()
+    }
+}
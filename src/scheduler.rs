@@ -0,0 +1,629 @@
+// std.
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// chrono.
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+// cron.
+use cron::Schedule as CronSchedule;
+
+// serde.
+use serde::{Deserialize, Serialize};
+
+// Tokio.
+use rocket::tokio;
+use rocket::tokio::sync::Notify;
+
+// logging.
+use log::{debug, error, info};
+
+// Own.
+use crate::store::{StateStore, StoreError};
+use crate::WebexClient;
+
+// ###################################################################
+// Scheduled job callback. Receives the client and the schedule payload.
+// ###################################################################
+
+pub type Job =
+    fn(WebexClient, serde_json::Value) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+// ###################################################################
+// Errors raised while scheduling a job.
+// ###################################################################
+
+#[derive(Debug)]
+pub enum SchedulerError {
+    InvalidCron(String),     // The cron expression could not be parsed.
+    InvalidTimezone(String), // The time zone is not a valid IANA name.
+    UnknownJob(String),      // No job was registered under that name.
+    OutOfRange(Duration),    // The delay goes past the representable dates.
+    Store(StoreError),       // The schedule could not be persisted.
+}
+
+impl fmt::Display for SchedulerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulerError::InvalidCron(e) => write!(f, "Invalid cron expression: {}", e),
+            SchedulerError::InvalidTimezone(tz) => write!(f, "Invalid time zone: {}", tz),
+            SchedulerError::UnknownJob(job) => write!(f, "Unknown job: {}", job),
+            SchedulerError::OutOfRange(delay) => write!(f, "Delay out of range: {:?}", delay),
+            SchedulerError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SchedulerError {}
+
+impl From<StoreError> for SchedulerError {
+    fn from(e: StoreError) -> Self {
+        SchedulerError::Store(e)
+    }
+}
+
+// ###################################################################
+// When a job runs.
+// ###################################################################
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Trigger {
+    // Cron expression with seconds, e.g. "0 30 9 * * Mon-Fri", in an IANA time zone.
+    Cron {
+        expression: String,
+        timezone: String,
+    },
+    // Fixed interval, in seconds.
+    Interval {
+        every: u64,
+    },
+    // Single run at the given instant.
+    Once {
+        at: DateTime<Utc>,
+    },
+}
+
+impl Trigger {
+    pub fn cron(expression: &str, timezone: &str) -> Trigger {
+        Trigger::Cron {
+            expression: expression.to_string(),
+            timezone: timezone.to_string(),
+        }
+    }
+
+    pub fn every(interval: Duration) -> Trigger {
+        Trigger::Interval {
+            every: interval.as_secs().max(1),
+        }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Single run after the delay. Fails when the instant can't be represented.
+    // ------------------------------------------------------------------------------
+
+    pub fn after(delay: Duration) -> Result<Trigger, SchedulerError> {
+        chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| Utc::now().checked_add_signed(delay))
+            .map(|at| Trigger::Once { at })
+            .ok_or(SchedulerError::OutOfRange(delay))
+    }
+
+    pub fn at(at: DateTime<Utc>) -> Trigger {
+        Trigger::Once { at }
+    }
+
+    // ------------------------------------------------------------------------------
+    // Next run strictly after the given instant. None when the trigger is done.
+    // ------------------------------------------------------------------------------
+
+    pub fn next_after(
+        &self,
+        after: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, SchedulerError> {
+        match self {
+            Trigger::Cron {
+                expression,
+                timezone,
+            } => {
+                let schedule = CronSchedule::from_str(expression)
+                    .map_err(|e| SchedulerError::InvalidCron(e.to_string()))?;
+                let timezone = Tz::from_str(timezone)
+                    .map_err(|_| SchedulerError::InvalidTimezone(timezone.clone()))?;
+                Ok(schedule
+                    .after(&after.with_timezone(&timezone))
+                    .next()
+                    .map(|next| next.with_timezone(&Utc)))
+            }
+            Trigger::Interval { every } => {
+                Ok(Some(after + chrono::Duration::seconds(*every as i64)))
+            }
+            Trigger::Once { at } => Ok((*at > after).then_some(*at)),
+        }
+    }
+}
+
+// ###################################################################
+// A job bound to its trigger.
+// ###################################################################
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Schedule {
+    pub id: String,
+    pub job: String, // Name the job was registered under.
+    pub trigger: Trigger,
+    pub payload: serde_json::Value,
+    pub next_run: DateTime<Utc>,
+    pub persistent: bool, // Saved in the state store to survive restarts.
+}
+
+// ###################################################################
+// Scheduler shared by the bot server and its handlers.
+// ###################################################################
+
+#[derive(Clone, Default)]
+pub struct Scheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+#[derive(Default)]
+struct SchedulerInner {
+    jobs: Mutex<HashMap<String, Job>>,
+    configs: Mutex<HashMap<String, serde_json::Value>>, // Settings of the jobs, by name.
+    schedules: Mutex<HashMap<String, Schedule>>,
+    store: Mutex<Option<Arc<dyn StateStore>>>,
+    wake: Notify,
+}
+
+impl Scheduler {
+    const NAMESPACE: &'static str = "schedules";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Register a job under a name. Schedules refer to jobs by name so they can be
+    // persisted and restored.
+    // ------------------------------------------------------------------------------
+
+    pub fn add_job(&self, name: &str, job: Job) {
+        self.inner
+            .jobs
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), job);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Settings shared by every schedule of a job, e.g. for the handlers creating
+    // them.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn set_job_config(&self, name: &str, config: serde_json::Value) {
+        self.inner
+            .configs
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), config);
+    }
+
+    pub(crate) fn job_config(&self, name: &str) -> Option<serde_json::Value> {
+        self.inner.configs.lock().unwrap().get(name).cloned()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Schedule a registered job. Returns the id of the schedule.
+    // ------------------------------------------------------------------------------
+
+    pub async fn schedule(
+        &self,
+        job: &str,
+        trigger: Trigger,
+        payload: serde_json::Value,
+        persistent: bool,
+    ) -> Result<String, SchedulerError> {
+        let schedule = self.prepare(job, trigger, payload, persistent)?;
+        self.save(&schedule).await?;
+        Ok(self.insert(schedule))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Schedule a job before the server runs. Persistent schedules are saved once the
+    // store is attached at launch.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn schedule_at_launch(
+        &self,
+        job: &str,
+        trigger: Trigger,
+        payload: serde_json::Value,
+        persistent: bool,
+    ) -> Result<String, SchedulerError> {
+        let schedule = self.prepare(job, trigger, payload, persistent)?;
+        Ok(self.insert(schedule))
+    }
+
+    fn prepare(
+        &self,
+        job: &str,
+        trigger: Trigger,
+        payload: serde_json::Value,
+        persistent: bool,
+    ) -> Result<Schedule, SchedulerError> {
+        if !self.inner.jobs.lock().unwrap().contains_key(job) {
+            return Err(SchedulerError::UnknownJob(job.to_string()));
+        }
+
+        // Fixed intervals run for the first time one interval from now.
+        let next_run = match &trigger {
+            Trigger::Once { at } => *at,
+            _ => trigger
+                .next_after(Utc::now())?
+                .ok_or_else(|| SchedulerError::InvalidCron("never runs".to_string()))?,
+        };
+
+        Ok(Schedule {
+            id: uuid::Uuid::new_v4().to_string(),
+            job: job.to_string(),
+            trigger,
+            payload,
+            next_run,
+            persistent,
+        })
+    }
+
+    fn insert(&self, schedule: Schedule) -> String {
+        let id = schedule.id.clone();
+        self.inner
+            .schedules
+            .lock()
+            .unwrap()
+            .insert(id.clone(), schedule);
+        self.inner.wake.notify_one();
+        id
+    }
+
+    // ------------------------------------------------------------------------------
+    // Remove a schedule. Returns whether it existed.
+    // ------------------------------------------------------------------------------
+
+    pub async fn cancel(&self, id: &str) -> Result<bool, SchedulerError> {
+        let removed = self.inner.schedules.lock().unwrap().remove(id);
+        if let Some(schedule) = &removed {
+            self.remove_persisted(schedule).await?;
+        }
+        self.inner.wake.notify_one();
+        Ok(removed.is_some())
+    }
+
+    // ------------------------------------------------------------------------------
+    // Snapshot of every pending schedule.
+    // ------------------------------------------------------------------------------
+
+    pub fn schedules(&self) -> Vec<Schedule> {
+        self.inner
+            .schedules
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Restore the persisted schedules and run the jobs as they become due.
+    // ------------------------------------------------------------------------------
+
+    pub async fn run(self, client: WebexClient) {
+        self.attach_store(client.store.clone()).await;
+        info!(
+            "Scheduler started with {} schedules",
+            self.schedules().len()
+        );
+
+        loop {
+            let now = Utc::now();
+            let due = self.take_due(now);
+            for schedule in due {
+                self.fire(&client, schedule, now).await;
+            }
+
+            // Sleep until the next schedule is due or a schedule changes.
+            let next = self
+                .inner
+                .schedules
+                .lock()
+                .unwrap()
+                .values()
+                .map(|schedule| schedule.next_run)
+                .min();
+            let sleep = match next {
+                Some(next) => (next - Utc::now()).to_std().unwrap_or(Duration::ZERO),
+                None => Duration::from_secs(3600),
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = self.inner.wake.notified() => {}
+                _ = client.shutdown.closed() => {
+                    info!("Scheduler stopped.");
+                    return;
+                }
+            }
+        }
+    }
+
+    // Remove and return the schedules due at the given instant.
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<Schedule> {
+        let mut schedules = self.inner.schedules.lock().unwrap();
+        let due = schedules
+            .values()
+            .filter(|schedule| schedule.next_run <= now)
+            .map(|schedule| schedule.id.clone())
+            .collect::<Vec<String>>();
+        due.iter().filter_map(|id| schedules.remove(id)).collect()
+    }
+
+    // Spawn the job and put the schedule back if it has to run again.
+    async fn fire(&self, client: &WebexClient, mut schedule: Schedule, now: DateTime<Utc>) {
+        let job = self.inner.jobs.lock().unwrap().get(&schedule.job).copied();
+        // Leave the schedule untouched when shutting down, so it still runs (or is
+        // restored) later.
+        let in_flight = match client.shutdown.track() {
+            Some(in_flight) => in_flight,
+            None => {
+                debug!("Shutting down, skipping job {}.", schedule.job);
+                self.inner
+                    .schedules
+                    .lock()
+                    .unwrap()
+                    .insert(schedule.id.clone(), schedule);
+                return;
+            }
+        };
+
+        match job {
+            Some(job) => {
+                debug!("Running job {} ({})", schedule.job, schedule.id);
+                let run = job(client.clone(), schedule.payload.clone());
+                tokio::spawn(async move {
+                    run.await;
+                    drop(in_flight);
+                });
+            }
+            None => error!("Job {} is not registered, skipping.", schedule.job),
+        }
+
+        let next_run = match schedule.trigger.next_after(now) {
+            Ok(next_run) => next_run,
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        };
+
+        let result = match next_run {
+            Some(next_run) => {
+                schedule.next_run = next_run;
+                let result = self.save(&schedule).await;
+                self.inner
+                    .schedules
+                    .lock()
+                    .unwrap()
+                    .insert(schedule.id.clone(), schedule);
+                result
+            }
+            None => self.remove_persisted(&schedule).await,
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+    }
+
+    // Persist the in-memory schedules and load the ones from previous runs.
+    async fn attach_store(&self, store: Arc<dyn StateStore>) {
+        *self.inner.store.lock().unwrap() = Some(store.clone());
+
+        for schedule in self.schedules() {
+            if let Err(e) = self.save(&schedule).await {
+                error!("{}", e);
+            }
+        }
+
+        let stored = match store.scan(Self::NAMESPACE, "").await {
+            Ok(stored) => stored,
+            Err(e) => {
+                error!("Could not restore the schedules: {}", e);
+                return;
+            }
+        };
+
+        let mut schedules = self.inner.schedules.lock().unwrap();
+        for (id, value) in stored {
+            match serde_json::from_value::<Schedule>(value) {
+                Ok(schedule) => {
+                    schedules.entry(id).or_insert(schedule);
+                }
+                Err(e) => error!("Could not restore schedule {}: {}", id, e),
+            }
+        }
+    }
+
+    async fn save(&self, schedule: &Schedule) -> Result<(), SchedulerError> {
+        if !schedule.persistent {
+            return Ok(());
+        }
+        if let Some(store) = self.store() {
+            store
+                .put_as(Self::NAMESPACE, &schedule.id, schedule, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn remove_persisted(&self, schedule: &Schedule) -> Result<(), SchedulerError> {
+        if !schedule.persistent {
+            return Ok(());
+        }
+        if let Some(store) = self.store() {
+            store.delete(Self::NAMESPACE, &schedule.id).await?;
+        }
+        Ok(())
+    }
+
+    fn store(&self) -> Option<Arc<dyn StateStore>> {
+        self.inner.store.lock().unwrap().clone()
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn noop(_: WebexClient, _: serde_json::Value) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(async {})
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn scheduler() -> Scheduler {
+        let scheduler = Scheduler::new();
+        scheduler.add_job("noop", noop);
+        scheduler
+    }
+
+    #[test]
+    fn cron_triggers_follow_their_time_zone() {
+        let trigger = Trigger::cron("0 30 9 * * *", "Europe/Madrid");
+        // UTC+1 in winter, UTC+2 in summer.
+        let next = trigger.next_after(utc(2024, 1, 15, 0, 0)).unwrap();
+        assert_eq!(next, Some(utc(2024, 1, 15, 8, 30)));
+        let next = trigger.next_after(utc(2024, 7, 15, 8, 0)).unwrap();
+        assert_eq!(next, Some(utc(2024, 7, 16, 7, 30)));
+
+        let weekdays = Trigger::cron("0 0 9 * * Mon-Fri", "America/New_York");
+        // Saturday 2024-01-13, next run on Monday at 14:00 UTC.
+        let next = weekdays.next_after(utc(2024, 1, 13, 12, 0)).unwrap();
+        assert_eq!(next, Some(utc(2024, 1, 15, 14, 0)));
+    }
+
+    #[test]
+    fn invalid_cron_triggers_are_reported() {
+        let now = Utc::now();
+        assert!(matches!(
+            Trigger::cron("every day", "UTC").next_after(now),
+            Err(SchedulerError::InvalidCron(_))
+        ));
+        assert!(matches!(
+            Trigger::cron("0 0 9 * * *", "Mars/Olympus").next_after(now),
+            Err(SchedulerError::InvalidTimezone(_))
+        ));
+        assert!(matches!(
+            scheduler().schedule_at_launch(
+                "missing",
+                Trigger::every(Duration::ZERO),
+                json!(null),
+                false
+            ),
+            Err(SchedulerError::UnknownJob(_))
+        ));
+    }
+
+    #[test]
+    fn intervals_and_single_runs() {
+        let now = utc(2024, 1, 15, 12, 0);
+        let every = Trigger::every(Duration::from_secs(90));
+        assert_eq!(
+            every.next_after(now).unwrap(),
+            Some(now + chrono::Duration::seconds(90))
+        );
+        // Sub-second intervals are rounded up to a second.
+        let fast = Trigger::every(Duration::from_millis(10));
+        assert_eq!(
+            fast.next_after(now).unwrap(),
+            Some(now + chrono::Duration::seconds(1))
+        );
+
+        let past = Trigger::at(now - chrono::Duration::minutes(1));
+        assert_eq!(past.next_after(now).unwrap(), None);
+        let future = Trigger::at(now + chrono::Duration::minutes(1));
+        assert_eq!(
+            future.next_after(now).unwrap(),
+            Some(now + chrono::Duration::minutes(1))
+        );
+        assert!(matches!(
+            Trigger::after(Duration::MAX),
+            Err(SchedulerError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn due_schedules_are_taken_once() {
+        let scheduler = scheduler();
+        let past = Trigger::at(Utc::now() - chrono::Duration::minutes(1));
+        let due = scheduler
+            .schedule_at_launch("noop", past, json!(null), false)
+            .unwrap();
+        let hourly = Trigger::every(Duration::from_secs(3600));
+        let later = scheduler
+            .schedule_at_launch("noop", hourly, json!(null), false)
+            .unwrap();
+
+        let taken = scheduler.take_due(Utc::now());
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].id, due);
+        assert!(scheduler.take_due(Utc::now()).is_empty());
+
+        let remaining = scheduler.schedules();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, later);
+    }
+
+    #[rocket::async_test]
+    async fn persistent_schedules_are_restored() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let hourly = Trigger::every(Duration::from_secs(3600));
+
+        let first = scheduler();
+        // Scheduled before the store is attached, saved once it is.
+        let kept = first
+            .schedule_at_launch("noop", hourly.clone(), json!({ "room": "a" }), true)
+            .unwrap();
+        first
+            .schedule_at_launch("noop", hourly.clone(), json!(null), false)
+            .unwrap();
+        first.attach_store(store.clone()).await;
+        let cancelled = first
+            .schedule("noop", hourly, json!(null), true)
+            .await
+            .unwrap();
+        assert!(first.cancel(&cancelled).await.unwrap());
+        assert!(!first.cancel(&cancelled).await.unwrap());
+
+        let second = scheduler();
+        second.attach_store(store).await;
+        let restored = second.schedules();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].id, kept);
+        assert_eq!(restored[0].payload, json!({ "room": "a" }));
+    }
+}
@@ -0,0 +1,12 @@
+// ###################################################################
+// Helpers for comparing secrets (tokens, signatures).
+// ###################################################################
+
+// ------------------------------------------------------------------------------
+// Compare in constant time for inputs of the same length, so a secret can't be
+// guessed byte by byte from the response times.
+// ------------------------------------------------------------------------------
+
+pub(crate) fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
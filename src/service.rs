@@ -9,7 +9,9 @@ use reqwest::header::CONTENT_TYPE;
 // Constants.
 // ###########################################################################
 
-pub const WEBEX_URI: &'static str = "https://webexapis.com/v1/";
+pub const WEBEX_URI: &str = "https://webexapis.com/v1/";
+pub const WDM_URI: &str = "https://wdm-a.wbx2.com/wdm/api/v1";
+pub const U2C_URI: &str = "https://u2c.wbx2.com/u2c/api/v1/";
 
 // ###########################################################################
 // Endpoint containers.
@@ -17,7 +19,9 @@ pub const WEBEX_URI: &'static str = "https://webexapis.com/v1/";
 
 mod endpoints {
     // Private crate to hold all types that the user shouldn't have to interact with.
-    use crate::types::{AttachmentAction, Message, Organization, Person, Room, Team};
+    use crate::types::{
+        AttachmentAction, Membership, Message, Organization, Person, Room, Team, Webhook,
+    };
     use serde::Deserialize;
     // Trait for API types. Has to be public due to trait bounds limitations on webex API, but hidden
     // in a private crate so users don't see it.
@@ -49,18 +53,30 @@ mod endpoints {
         const API_ENDPOINT: &'static str = "teams";
     }
 
+    impl Gettable for Membership {
+        const API_ENDPOINT: &'static str = "memberships";
+    }
+
+    impl Gettable for Webhook {
+        const API_ENDPOINT: &'static str = "webhooks";
+    }
+
     #[derive(Deserialize)]
     pub struct ListResult<T> {
         pub items: Vec<T>,
     }
 }
 
-use crate::types::{self, Message};
+use crate::metrics::Metrics;
+use crate::types::{
+    self, AttachmentAction, Catalog, CatalogReply, Device, DeviceList, Membership, Message, Person,
+    Webhook,
+};
 use http::HeaderMap;
 use reqwest::Client;
-use std::{mem::MaybeUninit, sync::Once};
+use std::sync::OnceLock;
 
-use self::endpoints::Gettable;
+use self::endpoints::{Gettable, ListResult};
 
 // ###########################################################################
 // Singleton class
@@ -73,26 +89,21 @@ pub struct Service {
 
 impl Service {
     pub fn get_instance() -> &'static Service {
-        static mut INSTANCE: MaybeUninit<Service> = MaybeUninit::uninit();
-        static ONCE: Once = Once::new();
-
-        unsafe {
-            ONCE.call_once(|| {
-                let mut headers = HeaderMap::new();
-                headers.insert(
-                    CONTENT_TYPE,
-                    HeaderValue::from_str("application/json").unwrap(),
-                );
-                headers.insert(ACCEPT, HeaderValue::from_str("application/json").unwrap());
-
-                INSTANCE.write(Service {
-                    client: Client::new(),
-                    headers,
-                });
-            });
-
-            INSTANCE.assume_init_ref()
-        }
+        static INSTANCE: OnceLock<Service> = OnceLock::new();
+
+        INSTANCE.get_or_init(|| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_str("application/json").unwrap(),
+            );
+            headers.insert(ACCEPT, HeaderValue::from_str("application/json").unwrap());
+
+            Service {
+                client: Client::new(),
+                headers,
+            }
+        })
     }
 }
 
@@ -100,7 +111,9 @@ impl Service {
 // Review the status for the response.
 // ###########################################################################
 
-pub fn review_status(response: &reqwest::Response) -> () {
+pub fn review_status(response: &reqwest::Response) {
+    Metrics::get_instance().record_api_call(response.status().as_u16());
+
     match response.status() {
         reqwest::StatusCode::OK => {
             log::debug!("Succesful request: {:?}", response)
@@ -132,12 +145,10 @@ pub async fn send_message(token: &str, message_out: &types::MessageOut) -> Messa
 
     review_status(&response);
 
-    let message = response
+    response
         .json::<Message>()
         .await
-        .expect("failed to convert struct from json");
-
-    return message;
+        .expect("failed to convert struct from json")
 }
 
 // ###########################################################################
@@ -162,10 +173,227 @@ pub async fn get_message_details(token: &str, message_id: &String) -> Message {
 
     review_status(&response);
 
-    let message = response
+    response
         .json::<Message>()
         .await
-        .expect("failed to convert struct from json");
+        .expect("failed to convert struct from json")
+}
+
+// ###########################################################################
+// Retrieve the inputs submitted through an adaptive card.
+// ###########################################################################
+
+pub async fn get_attachment_action(
+    token: &str,
+    action_id: &str,
+) -> Result<AttachmentAction, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!(
+            "{}{}/{}",
+            WEBEX_URI,
+            AttachmentAction::API_ENDPOINT,
+            action_id
+        ))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    response
+        .error_for_status()?
+        .json::<AttachmentAction>()
+        .await
+}
+
+// ###########################################################################
+// Retrieve detailed information from a specific person.
+// ###########################################################################
+
+pub async fn get_person_details(token: &str, person_id: &str) -> Result<Person, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!(
+            "{}{}/{}",
+            WEBEX_URI,
+            Person::API_ENDPOINT,
+            person_id
+        ))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    response.error_for_status()?.json::<Person>().await
+}
+
+// ###########################################################################
+// List the memberships of a room, optionally filtered by person.
+// ###########################################################################
+
+pub async fn list_memberships(
+    token: &str,
+    room_id: &str,
+    person_id: Option<&str>,
+) -> Result<Vec<Membership>, reqwest::Error> {
+    let mut query = vec![("roomId", room_id)];
+    if let Some(person_id) = person_id {
+        query.push(("personId", person_id));
+    }
+
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!("{}{}", WEBEX_URI, Membership::API_ENDPOINT))
+        .headers(client_service.headers.clone())
+        .query(&query)
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    Ok(response
+        .error_for_status()?
+        .json::<ListResult<Membership>>()
+        .await?
+        .items)
+}
+
+// ###########################################################################
+// Retrieve the details of the person owning the token.
+// ###########################################################################
+
+pub async fn get_my_details(token: &str) -> Result<Person, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!("{}{}/me", WEBEX_URI, Person::API_ENDPOINT))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    response.error_for_status()?.json::<Person>().await
+}
+
+// ###########################################################################
+// List the webhooks registered by the owner of the token.
+// ###########################################################################
+
+pub async fn list_webhooks(token: &str) -> Result<Vec<Webhook>, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!("{}{}", WEBEX_URI, Webhook::API_ENDPOINT))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    Ok(response
+        .error_for_status()?
+        .json::<ListResult<Webhook>>()
+        .await?
+        .items)
+}
+
+// ###########################################################################
+// Register a new device with WDM to receive events through Mercury.
+// ###########################################################################
+
+pub async fn register_device(
+    token: &str,
+    wdm_url: &str,
+    device: &Device,
+) -> Result<Device, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .post(format!("{}/devices", wdm_url.trim_end_matches('/')))
+        .headers(client_service.headers.clone())
+        .json(device)
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    response.error_for_status()?.json::<Device>().await
+}
+
+// ###########################################################################
+// Discover the service links (WDM, identity...) available to the token.
+// ###########################################################################
+
+pub async fn get_catalog(token: &str) -> Result<Catalog, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!("{}catalog", U2C_URI))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    Ok(response
+        .error_for_status()?
+        .json::<CatalogReply>()
+        .await?
+        .service_links)
+}
+
+// ###########################################################################
+// List the devices registered with WDM by the owner of the token.
+// ###########################################################################
+
+pub async fn list_devices(token: &str, wdm_url: &str) -> Result<Vec<Device>, reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .get(format!("{}/devices", wdm_url.trim_end_matches('/')))
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
+
+    Ok(response
+        .error_for_status()?
+        .json::<DeviceList>()
+        .await?
+        .devices)
+}
+
+// ###########################################################################
+// Delete a device registration, given its url.
+// ###########################################################################
+
+pub async fn delete_device(token: &str, device_url: &str) -> Result<(), reqwest::Error> {
+    let client_service = Service::get_instance();
+    let response = client_service
+        .client
+        .delete(device_url)
+        .headers(client_service.headers.clone())
+        .bearer_auth(token)
+        .send()
+        .await?;
+
+    review_status(&response);
 
-    return message;
+    response.error_for_status()?;
+    Ok(())
 }
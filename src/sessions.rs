@@ -0,0 +1,268 @@
+// std.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Tokio.
+use rocket::tokio::sync::oneshot;
+use rocket::tokio::time;
+
+// logging.
+use log::debug;
+
+// Own.
+use crate::dispatcher::{leading_mention, release_worker};
+use crate::types::Message;
+
+// ###################################################################
+// Errors returned while waiting for the next message of a user.
+// ###################################################################
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    Timeout,   // The user did not answer in time.
+    Cancelled, // The user answered "cancel" or the wait was cancelled.
+    Missing,   // The message has no room or person to wait on.
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Timeout => write!(f, "The conversation timed out."),
+            SessionError::Cancelled => write!(f, "The conversation was cancelled."),
+            SessionError::Missing => write!(f, "The message has no room or person."),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+// ###################################################################
+// Conversations awaiting the next message of a (room, person) pair.
+// ###################################################################
+
+type SessionKey = (String, String);
+
+#[derive(Clone, Default)]
+pub struct Sessions {
+    waiting: Arc<Mutex<HashMap<SessionKey, oneshot::Sender<Message>>>>,
+}
+
+impl Sessions {
+    const CANCEL: &'static str = "cancel";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Wait for the next message the person posts in the room. A previous wait on
+    // the same pair is cancelled.
+    // ------------------------------------------------------------------------------
+
+    pub async fn wait_for(
+        &self,
+        room_id: &str,
+        person_id: &str,
+        timeout: Duration,
+    ) -> Result<Message, SessionError> {
+        let key = (room_id.to_string(), person_id.to_string());
+        let (sender, receiver) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(key.clone(), sender);
+        // Don't hold the worker for the whole conversation.
+        release_worker();
+
+        let reply = match time::timeout(timeout, receiver).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => return Err(SessionError::Cancelled),
+            Err(_) => {
+                self.remove_closed(&key);
+                return Err(SessionError::Timeout);
+            }
+        };
+
+        // Let the user bail out of a multi-step dialogue, with or without the
+        // bot mention in front.
+        let text = reply.text.as_deref().unwrap_or_default().trim_start();
+        let text = leading_mention(&reply)
+            .and_then(|mention| text.strip_prefix(mention.as_str()))
+            .unwrap_or(text);
+        if text.trim().eq_ignore_ascii_case(Self::CANCEL) {
+            return Err(SessionError::Cancelled);
+        }
+
+        Ok(reply)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Cancel the conversation of a person in a room, if any.
+    // ------------------------------------------------------------------------------
+
+    pub fn cancel(&self, room_id: &str, person_id: &str) -> bool {
+        self.waiting
+            .lock()
+            .unwrap()
+            .remove(&(room_id.to_string(), person_id.to_string()))
+            .is_some()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Whether a conversation is waiting for the next message of the person.
+    // ------------------------------------------------------------------------------
+
+    pub fn is_waiting(&self, room_id: &str, person_id: &str) -> bool {
+        self.waiting
+            .lock()
+            .unwrap()
+            .contains_key(&(room_id.to_string(), person_id.to_string()))
+    }
+
+    // ------------------------------------------------------------------------------
+    // Cancel every ongoing conversation, e.g. when the server shuts down.
+    // ------------------------------------------------------------------------------
+
+    pub fn cancel_all(&self) {
+        self.waiting.lock().unwrap().clear();
+    }
+
+    // ------------------------------------------------------------------------------
+    // Hand an incoming message to the conversation waiting for it. The message
+    // is given back when nobody is waiting.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) fn deliver(&self, message: Message) -> Option<Message> {
+        let key = match (&message.room_id, &message.person_id) {
+            (Some(room_id), Some(person_id)) => (room_id.clone(), person_id.clone()),
+            _ => return Some(message),
+        };
+
+        let sender = self.waiting.lock().unwrap().remove(&key);
+        match sender {
+            Some(sender) => {
+                debug!("Delivering message to the conversation of {:?}", key);
+                sender.send(message).err()
+            }
+            None => Some(message),
+        }
+    }
+
+    // Drop the entry of a wait that is over, unless it was already replaced.
+    fn remove_closed(&self, key: &SessionKey) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if waiting.get(key).is_some_and(|sender| sender.is_closed()) {
+            waiting.remove(key);
+        }
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::{self, task::JoinHandle};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn message(text: &str, html: Option<&str>) -> Message {
+        Message {
+            room_id: Some("room".to_string()),
+            person_id: Some("person".to_string()),
+            text: Some(text.to_string()),
+            html: html.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    // Start waiting for the next message of the person, once registered.
+    async fn waiting(sessions: &Sessions) -> JoinHandle<Result<Message, SessionError>> {
+        let waiter = sessions.clone();
+        let handle = tokio::spawn(async move { waiter.wait_for("room", "person", TIMEOUT).await });
+        while !sessions.is_waiting("room", "person") {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[rocket::async_test]
+    async fn the_next_message_goes_to_the_waiting_conversation() {
+        let sessions = Sessions::new();
+        let handle = waiting(&sessions).await;
+
+        assert!(sessions.deliver(message("staging", None)).is_none());
+        let reply = handle.await.unwrap().unwrap();
+        assert_eq!(reply.text.as_deref(), Some("staging"));
+        assert!(!sessions.is_waiting("room", "person"));
+
+        // Nobody waits anymore, the message is given back.
+        assert!(sessions.deliver(message("staging", None)).is_some());
+        let mut other = message("staging", None);
+        other.person_id = None;
+        assert!(sessions.deliver(other).is_some());
+    }
+
+    #[rocket::async_test]
+    async fn cancel_answers_end_the_conversation() {
+        let mention = r#"<p><spark-mention data-object-type="person" data-object-id="bot">Futbolito Bot</spark-mention> cancel</p>"#;
+        let cases = [
+            ("cancel", None, true),
+            ("  Cancel ", None, true),
+            ("Futbolito Bot cancel", Some(mention), true),
+            ("don't cancel", None, false),
+            ("cancel it", None, false),
+            ("Futbolito Bot cancel", None, false),
+        ];
+        for (text, html, cancelled) in cases {
+            let sessions = Sessions::new();
+            let handle = waiting(&sessions).await;
+            sessions.deliver(message(text, html));
+            let result = handle.await.unwrap();
+            assert_eq!(
+                result.is_err_and(|e| e == SessionError::Cancelled),
+                cancelled,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn unanswered_conversations_time_out() {
+        let sessions = Sessions::new();
+        let result = sessions
+            .wait_for("room", "person", Duration::from_millis(10))
+            .await;
+        assert_eq!(result.unwrap_err(), SessionError::Timeout);
+        assert!(!sessions.is_waiting("room", "person"));
+    }
+
+    #[rocket::async_test]
+    async fn a_new_wait_replaces_the_previous_one() {
+        let sessions = Sessions::new();
+        let first = waiting(&sessions).await;
+        let second = sessions.clone();
+        let second = tokio::spawn(async move { second.wait_for("room", "person", TIMEOUT).await });
+
+        assert_eq!(first.await.unwrap().unwrap_err(), SessionError::Cancelled);
+        sessions.deliver(message("production", None));
+        let reply = second.await.unwrap().unwrap();
+        assert_eq!(reply.text.as_deref(), Some("production"));
+    }
+
+    #[rocket::async_test]
+    async fn waits_can_be_cancelled() {
+        let sessions = Sessions::new();
+        let handle = waiting(&sessions).await;
+        assert!(sessions.cancel("room", "person"));
+        assert!(!sessions.cancel("room", "person"));
+        assert_eq!(handle.await.unwrap().unwrap_err(), SessionError::Cancelled);
+
+        let handle = waiting(&sessions).await;
+        sessions.cancel_all();
+        assert!(!sessions.is_waiting("room", "person"));
+        assert_eq!(handle.await.unwrap().unwrap_err(), SessionError::Cancelled);
+    }
+}
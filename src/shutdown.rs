@@ -0,0 +1,181 @@
+// std.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+// Rocket.
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::sync::{watch, Notify};
+use rocket::tokio::time;
+use rocket::{Orbit, Rocket};
+
+// logging.
+use log::{error, info, warn};
+
+// Own.
+use crate::WebexClient;
+
+// ###################################################################
+// Shutdown coordination shared by the server, handlers and jobs.
+// ###################################################################
+
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<ShutdownInner>,
+}
+
+struct ShutdownInner {
+    closing: watch::Sender<bool>,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    rocket: OnceLock<rocket::Shutdown>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            inner: Arc::new(ShutdownInner {
+                closing: watch::channel(false).0,
+                in_flight: AtomicUsize::new(0),
+                idle: Notify::new(),
+                rocket: OnceLock::new(),
+            }),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Request a graceful shutdown of the bot server.
+    // ------------------------------------------------------------------------------
+
+    pub fn trigger(&self) {
+        match self.inner.rocket.get() {
+            // The shutdown fairing takes care of the draining.
+            Some(rocket) => rocket.clone().notify(),
+            None => self.begin(),
+        }
+    }
+
+    pub fn is_closing(&self) -> bool {
+        *self.inner.closing.borrow()
+    }
+
+    // ------------------------------------------------------------------------------
+    // Resolve once the shutdown has begun. Long running tasks (websocket clients,
+    // loops) select on it to stop cleanly.
+    // ------------------------------------------------------------------------------
+
+    pub async fn closed(&self) {
+        let mut closing = self.inner.closing.subscribe();
+        let _ = closing.wait_for(|closing| *closing).await;
+    }
+
+    // ------------------------------------------------------------------------------
+    // Mark a unit of work (handler, job) as running until the guard is dropped.
+    // None once the shutdown has begun, so no new work starts.
+    // ------------------------------------------------------------------------------
+
+    pub fn track(&self) -> Option<InFlight> {
+        if self.is_closing() {
+            return None;
+        }
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        Some(InFlight {
+            inner: self.inner.clone(),
+        })
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn begin(&self) {
+        self.inner.closing.send_replace(true);
+    }
+
+    // ------------------------------------------------------------------------------
+    // Wait for the tracked work to finish. False if the deadline expired first.
+    // ------------------------------------------------------------------------------
+
+    pub(crate) async fn drain(&self, deadline: Duration) -> bool {
+        let idle = async {
+            loop {
+                let notified = self.inner.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        time::timeout(deadline, idle).await.is_ok()
+    }
+}
+
+// ###################################################################
+// Guard of a tracked unit of work.
+// ###################################################################
+
+pub struct InFlight {
+    inner: Arc<ShutdownInner>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+// ###################################################################
+// Fairing draining the bot when rocket shuts down (ctrl-c, SIGTERM or
+// `Shutdown::trigger`).
+// ###################################################################
+
+pub(crate) struct ShutdownFairing {
+    pub client: WebexClient,
+    pub deadline: Duration,
+}
+
+#[rocket::async_trait]
+impl Fairing for ShutdownFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Webex bot graceful shutdown",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let _ = self.client.shutdown.inner.rocket.set(rocket.shutdown());
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        let shutdown = &self.client.shutdown;
+        shutdown.begin();
+        self.client.sessions.cancel_all();
+
+        info!(
+            "Shutting down, waiting for {} running handlers and jobs.",
+            shutdown.in_flight()
+        );
+        if shutdown.drain(self.deadline).await {
+            info!("Every handler and job finished.");
+        } else {
+            warn!(
+                "Shutdown deadline expired with {} handlers and jobs still running.",
+                shutdown.in_flight()
+            );
+        }
+
+        // Write back what the store still holds in memory.
+        if let Err(e) = self.client.store.flush().await {
+            error!("Could not flush the state store: {}", e);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+// std.
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// serde.
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// chrono.
+use chrono::Utc;
+
+// Tokio.
+use rocket::tokio;
+
+#[cfg(feature = "file-store")]
+mod file;
+#[cfg(feature = "sqlite-store")]
+mod sqlite;
+
+#[cfg(feature = "file-store")]
+pub use file::FileStore;
+#[cfg(feature = "sqlite-store")]
+pub use sqlite::SqliteStore;
+
+// ###################################################################
+// Errors raised by the storage backends.
+// ###################################################################
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(std::io::Error),
+    Serde(serde_json::Error),
+    Backend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "State store I/O error: {}", e),
+            StoreError::Serde(e) => write!(f, "State store serialization error: {}", e),
+            StoreError::Backend(e) => write!(f, "State store backend error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Serde(e)
+    }
+}
+
+// ###################################################################
+// Key/value storage shared by the bot and its handlers.
+// ###################################################################
+
+/**
+ * Values are JSON documents grouped by namespace, so unrelated features
+ * (sessions, subscriptions, per-room settings...) can share a backend.
+ * Entries written with a TTL are no longer visible once it elapses; they
+ * are removed by a periodic sweep. Backends doing blocking I/O run it off
+ * the async workers; the in-memory ones answer right away.
+ */
+pub trait StateStore: Send + Sync {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, Option<Value>>;
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, ()>;
+    // Returns whether the key existed.
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, bool>;
+    // Every live entry of the namespace whose key starts with the prefix.
+    fn scan<'a>(
+        &'a self,
+        namespace: &'a str,
+        prefix: &'a str,
+    ) -> StoreFuture<'a, Vec<(String, Value)>>;
+    // Writes the value unless a live entry exists and returns whether it did.
+    // Backends shared between replicas should make it atomic.
+    fn insert_if_absent<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        Box::pin(async move {
+            if self.get(namespace, key).await?.is_some() {
+                return Ok(false);
+            }
+            self.put(namespace, key, value, ttl).await?;
+            Ok(true)
+        })
+    }
+    // Persist the pending writes, called on shutdown.
+    fn flush(&self) -> StoreFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// Future returned by the store operations, shareable like the handler ones.
+pub type StoreFuture<'a, T> =
+    Pin<Box<dyn Future<Output = Result<T, StoreError>> + Send + Sync + 'a>>;
+
+impl dyn StateStore {
+    // ------------------------------------------------------------------------------
+    // Typed helpers over the JSON values.
+    // ------------------------------------------------------------------------------
+
+    pub async fn get_as<T: DeserializeOwned>(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<T>, StoreError> {
+        match self.get(namespace, key).await? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_as<'a, T: Serialize>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, ()> {
+        match serde_json::to_value(value) {
+            Ok(value) => self.put(namespace, key, value, ttl),
+            Err(e) => Box::pin(async move { Err(e.into()) }),
+        }
+    }
+}
+
+// ###################################################################
+// Stored value with its optional expiration (unix milliseconds).
+// ###################################################################
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Entry {
+    pub value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl Entry {
+    pub fn new(value: Value, ttl: Option<Duration>) -> Self {
+        Entry {
+            value,
+            expires_at: ttl.map(expiration),
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.expires_at.is_none_or(|at| at > now())
+    }
+}
+
+pub(crate) fn now() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+pub(crate) fn expiration(ttl: Duration) -> i64 {
+    now().saturating_add(i64::try_from(ttl.as_millis()).unwrap_or(i64::MAX))
+}
+
+pub(crate) type Namespaces = HashMap<String, HashMap<String, Entry>>;
+
+// ------------------------------------------------------------------------------
+// Drop the expired entries, returning whether any was.
+// ------------------------------------------------------------------------------
+
+pub(crate) fn remove_expired(namespaces: &mut Namespaces) -> bool {
+    let before: usize = namespaces.values().map(HashMap::len).sum();
+    for namespace in namespaces.values_mut() {
+        namespace.retain(|_, entry| entry.is_alive());
+    }
+    namespaces.retain(|_, namespace| !namespace.is_empty());
+    namespaces.values().map(HashMap::len).sum::<usize>() != before
+}
+
+// ###################################################################
+// Background task running a maintenance function periodically, while
+// the store it belongs to is alive.
+// ###################################################################
+
+#[derive(Default)]
+pub(crate) struct Sweeper {
+    started: AtomicBool,
+}
+
+impl Sweeper {
+    // How often the expired entries are removed.
+    pub const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+    // ------------------------------------------------------------------------------
+    // Start the task on first use, from within the runtime. `run` is executed on
+    // the blocking pool.
+    // ------------------------------------------------------------------------------
+
+    pub fn ensure_started<T: Send + Sync + 'static>(
+        &self,
+        target: &Arc<T>,
+        interval: Duration,
+        run: fn(&T),
+    ) {
+        if self.started.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            self.started.store(false, Ordering::Release);
+            return;
+        };
+
+        let target = Arc::downgrade(target);
+        runtime.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(target) = target.upgrade() else {
+                    break;
+                };
+                if tokio::task::spawn_blocking(move || run(&target))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+// ###################################################################
+// In-memory store. Default for the bot server, lost on restart.
+// ###################################################################
+
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Arc<Mutex<Namespaces>>,
+    sweeper: Sweeper,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sweep(entries: &Mutex<Namespaces>) {
+        remove_expired(&mut entries.lock().unwrap());
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, Option<Value>> {
+        let entries = self.entries.lock().unwrap();
+        let value = entries
+            .get(namespace)
+            .and_then(|namespace| namespace.get(key))
+            .filter(|entry| entry.is_alive())
+            .map(|entry| entry.value.clone());
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, ()> {
+        self.sweeper
+            .ensure_started(&self.entries, Sweeper::EXPIRY_INTERVAL, Self::sweep);
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_string(), Entry::new(value, ttl));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, bool> {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries
+            .get_mut(namespace)
+            .and_then(|namespace| namespace.remove(key))
+            .is_some_and(|entry| entry.is_alive());
+        Box::pin(async move { Ok(removed) })
+    }
+
+    fn scan<'a>(
+        &'a self,
+        namespace: &'a str,
+        prefix: &'a str,
+    ) -> StoreFuture<'a, Vec<(String, Value)>> {
+        let entries = self.entries.lock().unwrap();
+        let found = entries
+            .get(namespace)
+            .map(|namespace| {
+                namespace
+                    .iter()
+                    .filter(|(key, entry)| key.starts_with(prefix) && entry.is_alive())
+                    .map(|(key, entry)| (key.clone(), entry.value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn insert_if_absent<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        self.sweeper
+            .ensure_started(&self.entries, Sweeper::EXPIRY_INTERVAL, Self::sweep);
+        let mut entries = self.entries.lock().unwrap();
+        let namespace = entries.entry(namespace.to_string()).or_default();
+        let inserted = !namespace.get(key).is_some_and(|entry| entry.is_alive());
+        if inserted {
+            namespace.insert(key.to_string(), Entry::new(value, ttl));
+        }
+        Box::pin(async move { Ok(inserted) })
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn expired_entries_are_hidden_then_swept() {
+        let store = MemoryStore::new();
+        let value = Value::from("x");
+        store
+            .put("sessions", "old", value.clone(), Some(Duration::ZERO))
+            .await
+            .unwrap();
+        store.put("sessions", "new", value, None).await.unwrap();
+
+        assert_eq!(store.get("sessions", "old").await.unwrap(), None);
+        assert_eq!(store.scan("sessions", "").await.unwrap().len(), 1);
+
+        MemoryStore::sweep(&store.entries);
+        assert_eq!(store.entries.lock().unwrap()["sessions"].len(), 1);
+    }
+}
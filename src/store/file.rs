@@ -0,0 +1,209 @@
+// std.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// serde.
+use serde_json::Value;
+
+// Tokio.
+use rocket::tokio;
+
+// logging.
+use log::error;
+
+// Own.
+use super::{remove_expired, Entry, Namespaces, StateStore, StoreError, StoreFuture, Sweeper};
+
+// ###################################################################
+// JSON file store. Kept in memory and written back in the background
+// shortly after a change, and on shutdown.
+// ###################################################################
+
+pub struct FileStore {
+    inner: Arc<FileInner>,
+    flusher: Sweeper, // Writes the changes back.
+    sweeper: Sweeper, // Drops the expired entries.
+}
+
+struct FileInner {
+    path: PathBuf,
+    entries: Mutex<Namespaces>,
+    dirty: AtomicBool, // Changed since the last write.
+}
+
+impl FileStore {
+    // How long changes may stay in memory only.
+    const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+    // ------------------------------------------------------------------------------
+    // Open (or create on the first write) the JSON file at the given path.
+    // ------------------------------------------------------------------------------
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<FileStore, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match fs::read_to_string(&path) {
+            Ok(contents) if !contents.trim().is_empty() => serde_json::from_str(&contents)?,
+            Ok(_) => Namespaces::new(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Namespaces::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(FileStore {
+            inner: Arc::new(FileInner {
+                path,
+                entries: Mutex::new(entries),
+                dirty: AtomicBool::new(false),
+            }),
+            flusher: Sweeper::default(),
+            sweeper: Sweeper::default(),
+        })
+    }
+
+    // Mark the entries as changed and make sure the background tasks run.
+    fn changed(&self) {
+        self.inner.dirty.store(true, Ordering::Release);
+        self.flusher
+            .ensure_started(&self.inner, Self::FLUSH_INTERVAL, FileInner::flush_logged);
+        self.sweeper
+            .ensure_started(&self.inner, Sweeper::EXPIRY_INTERVAL, FileInner::sweep);
+    }
+}
+
+impl FileInner {
+    // ------------------------------------------------------------------------------
+    // Write the entries to a temporary file and swap it in place, if they changed.
+    // ------------------------------------------------------------------------------
+
+    fn flush(&self) -> Result<(), StoreError> {
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let contents = {
+            let mut entries = self.entries.lock().unwrap();
+            remove_expired(&mut entries);
+            serde_json::to_vec_pretty(&*entries)
+        };
+
+        let written = contents.map_err(StoreError::from).and_then(|contents| {
+            let temporary = self.path.with_extension("tmp");
+            fs::write(&temporary, contents)?;
+            fs::rename(&temporary, &self.path)?;
+            Ok(())
+        });
+        // Try again on the next round.
+        if written.is_err() {
+            self.dirty.store(true, Ordering::Release);
+        }
+        written
+    }
+
+    fn flush_logged(&self) {
+        if let Err(e) = self.flush() {
+            error!("Could not write {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn sweep(&self) {
+        if remove_expired(&mut self.entries.lock().unwrap()) {
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+}
+
+impl StateStore for FileStore {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, Option<Value>> {
+        let entries = self.inner.entries.lock().unwrap();
+        let value = entries
+            .get(namespace)
+            .and_then(|namespace| namespace.get(key))
+            .filter(|entry| entry.is_alive())
+            .map(|entry| entry.value.clone());
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, ()> {
+        self.inner
+            .entries
+            .lock()
+            .unwrap()
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_string(), Entry::new(value, ttl));
+        self.changed();
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, bool> {
+        let removed = self
+            .inner
+            .entries
+            .lock()
+            .unwrap()
+            .get_mut(namespace)
+            .and_then(|namespace| namespace.remove(key));
+        if removed.is_some() {
+            self.changed();
+        }
+        let removed = removed.is_some_and(|entry| entry.is_alive());
+        Box::pin(async move { Ok(removed) })
+    }
+
+    fn scan<'a>(
+        &'a self,
+        namespace: &'a str,
+        prefix: &'a str,
+    ) -> StoreFuture<'a, Vec<(String, Value)>> {
+        let entries = self.inner.entries.lock().unwrap();
+        let found = entries
+            .get(namespace)
+            .map(|namespace| {
+                namespace
+                    .iter()
+                    .filter(|(key, entry)| key.starts_with(prefix) && entry.is_alive())
+                    .map(|(key, entry)| (key.clone(), entry.value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn insert_if_absent<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        let inserted = {
+            let mut entries = self.inner.entries.lock().unwrap();
+            let namespace = entries.entry(namespace.to_string()).or_default();
+            let inserted = !namespace.get(key).is_some_and(|entry| entry.is_alive());
+            if inserted {
+                namespace.insert(key.to_string(), Entry::new(value, ttl));
+            }
+            inserted
+        };
+        if inserted {
+            self.changed();
+        }
+        Box::pin(async move { Ok(inserted) })
+    }
+
+    fn flush(&self) -> StoreFuture<'_, ()> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || inner.flush())
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?
+        })
+    }
+}
@@ -0,0 +1,234 @@
+// std.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// serde.
+use serde_json::Value;
+
+// SQLite.
+use rusqlite::{params, Connection, OptionalExtension};
+
+// Tokio.
+use rocket::tokio;
+
+// logging.
+use log::error;
+
+// Own.
+use super::{expiration, now, StateStore, StoreError, StoreFuture, Sweeper};
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+// ###################################################################
+// SQLite store. Every namespace lives in the same table. Queries run
+// on the blocking pool.
+// ###################################################################
+
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+    sweeper: Sweeper, // Deletes the expired rows.
+}
+
+impl SqliteStore {
+    const SCHEMA: &'static str = "CREATE TABLE IF NOT EXISTS state (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        expires_at INTEGER,
+        PRIMARY KEY (namespace, key)
+    )";
+
+    // ------------------------------------------------------------------------------
+    // Open (or create) the database at the given path.
+    // ------------------------------------------------------------------------------
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, StoreError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    // ------------------------------------------------------------------------------
+    // Database living only as long as the store, mostly useful for tests.
+    // ------------------------------------------------------------------------------
+
+    pub fn open_in_memory() -> Result<SqliteStore, StoreError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<SqliteStore, StoreError> {
+        connection.execute(Self::SCHEMA, [])?;
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+            sweeper: Sweeper::default(),
+        })
+    }
+
+    // Run a query on the blocking pool.
+    async fn run<T, F>(&self, query: F) -> Result<T, StoreError>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce(&Connection) -> Result<T, StoreError> + Send + Sync + 'static,
+    {
+        self.sweeper
+            .ensure_started(&self.connection, Sweeper::EXPIRY_INTERVAL, Self::sweep);
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || query(&connection.lock().unwrap()))
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?
+    }
+
+    fn sweep(connection: &Mutex<Connection>) {
+        let deleted = connection.lock().unwrap().execute(
+            "DELETE FROM state WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            params![now()],
+        );
+        if let Err(e) = deleted {
+            error!("Could not delete the expired entries: {}", e);
+        }
+    }
+}
+
+impl StateStore for SqliteStore {
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, Option<Value>> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        Box::pin(async move {
+            let value = self
+                .run(move |connection| {
+                    Ok(connection
+                        .query_row(
+                            "SELECT value FROM state WHERE namespace = ?1 AND key = ?2
+                             AND (expires_at IS NULL OR expires_at > ?3)",
+                            params![namespace, key, now()],
+                            |row| row.get::<_, String>(0),
+                        )
+                        .optional()?)
+                })
+                .await?;
+
+            match value {
+                Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn put<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, ()> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        Box::pin(self.run(move |connection| {
+            connection.execute(
+                "INSERT OR REPLACE INTO state (namespace, key, value, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![namespace, key, value.to_string(), ttl.map(expiration)],
+            )?;
+            Ok(())
+        }))
+    }
+
+    fn delete<'a>(&'a self, namespace: &'a str, key: &'a str) -> StoreFuture<'a, bool> {
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        Box::pin(self.run(move |connection| {
+            let removed = connection.execute(
+                "DELETE FROM state WHERE namespace = ?1 AND key = ?2
+                 AND (expires_at IS NULL OR expires_at > ?3)",
+                params![namespace, key, now()],
+            )?;
+            Ok(removed > 0)
+        }))
+    }
+
+    fn scan<'a>(
+        &'a self,
+        namespace: &'a str,
+        prefix: &'a str,
+    ) -> StoreFuture<'a, Vec<(String, Value)>> {
+        let (namespace, prefix) = (namespace.to_string(), prefix.to_string());
+        Box::pin(self.run(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT key, value FROM state WHERE namespace = ?1
+                 AND substr(key, 1, length(?2)) = ?2
+                 AND (expires_at IS NULL OR expires_at > ?3)",
+            )?;
+            let rows = statement.query_map(params![namespace, prefix, now()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+
+            let mut entries = Vec::new();
+            for row in rows {
+                let (key, value) = row?;
+                entries.push((key, serde_json::from_str(&value)?));
+            }
+            Ok(entries)
+        }))
+    }
+
+    fn insert_if_absent<'a>(
+        &'a self,
+        namespace: &'a str,
+        key: &'a str,
+        value: Value,
+        ttl: Option<Duration>,
+    ) -> StoreFuture<'a, bool> {
+        // Expired rows the sweep has not deleted yet are replaced.
+        let (namespace, key) = (namespace.to_string(), key.to_string());
+        Box::pin(self.run(move |connection| {
+            let inserted = connection.execute(
+                "INSERT INTO state (namespace, key, value, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (namespace, key) DO UPDATE
+                 SET value = excluded.value, expires_at = excluded.expires_at
+                 WHERE state.expires_at IS NOT NULL AND state.expires_at <= ?5",
+                params![
+                    namespace,
+                    key,
+                    value.to_string(),
+                    ttl.map(expiration),
+                    now()
+                ],
+            )?;
+            Ok(inserted > 0)
+        }))
+    }
+}
+
+// ###################################################################
+// Unit tests.
+// ###################################################################
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn insert_if_absent_replaces_expired_rows() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let value = Value::from(1);
+
+        assert!(store
+            .insert_if_absent("events", "a", value.clone(), Some(Duration::ZERO))
+            .await
+            .unwrap());
+        // Expired but not swept yet.
+        assert!(store
+            .insert_if_absent("events", "a", value.clone(), None)
+            .await
+            .unwrap());
+        assert!(!store
+            .insert_if_absent("events", "a", value, None)
+            .await
+            .unwrap());
+        assert_eq!(
+            store.get("events", "a").await.unwrap(),
+            Some(Value::from(1))
+        );
+    }
+}
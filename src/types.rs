@@ -19,6 +19,8 @@ use std::pin::Pin;
 pub struct Register {
     pub user_id: u16,
     pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>, // Device details, e.g. firmware or location.
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,12 +40,19 @@ pub struct Publish {
 // ###########################################################################
 
 pub type ArgTuple = Vec<(std::string::String, std::string::String)>;
+pub type HandlerError = Box<dyn std::error::Error + Send + Sync>;
+pub type HandlerResult = Result<(), HandlerError>;
 pub type Callback = fn(
     WebexClient,
     Message,
     ArgTuple,
     ArgTuple,
-) -> Pin<Box<dyn Future<Output = ()> + Send + Sync + 'static>>;
+) -> Pin<Box<dyn Future<Output = HandlerResult> + Send + Sync + 'static>>;
+pub type CardActionCallback =
+    fn(
+        WebexClient,
+        AttachmentAction,
+    ) -> Pin<Box<dyn Future<Output = HandlerResult> + Send + Sync + 'static>>;
 
 // ###################################################################
 // Define the Argument trait
@@ -184,14 +193,71 @@ pub struct Team {
     pub description: Option<String>, // Team description.
 }
 
+// Room membership information.
+//-----------------------------------------------------------------------------------------------
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Membership {
+    pub id: String,                  // A unique identifier for the membership.
+    pub room_id: String,             // The room ID.
+    pub person_id: String,           // The person ID.
+    pub person_email: String,        // The email address of the person.
+    pub person_display_name: String, // The display name of the person.
+    pub person_org_id: String,       // The organization ID of the person.
+    pub is_moderator: bool,          // Whether or not the participant is a room moderator.
+    pub created: String,             // The date and time when the membership was created.
+}
+
+// Webhook registration information.
+//-----------------------------------------------------------------------------------------------
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Webhook {
+    pub id: String,         // A unique identifier for the webhook.
+    pub name: String,       // A user-friendly name for the webhook.
+    pub target_url: String, // The URL that receives POST requests for each event.
+    pub resource: String,   // The resource type for the webhook.
+    pub event: String,      // The event type for the webhook.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>, // The filter that defines the webhook scope.
+    pub status: String,     // The status of the webhook ("active" or "inactive").
+    pub created: String,    // The date and time the webhook was registered.
+}
+
+// Devices registered by the owner of a token.
+//-----------------------------------------------------------------------------------------------
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
+pub(crate) struct DeviceList {
+    pub devices: Vec<Device>,
+}
+
+// WDM device registration, required to receive events through Mercury.
+//-----------------------------------------------------------------------------------------------
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Device {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub url: String, // Device URL, used to refresh or delete the registration.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub web_socket_url: String, // Mercury websocket the events of the device are pushed to.
+    pub device_name: String,     // A user-friendly name for the device.
+    pub device_type: String,     // The type of device, such as "DESKTOP".
+    pub localized_model: String, // The localized model of the device.
+    pub model: String,           // The model of the device.
+    pub name: String,            // The name of the device.
+    pub system_name: String,     // The operating system of the device.
+    pub system_version: String,  // The operating system version of the device.
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct CatalogReply {
     pub service_links: Catalog,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case", default)]
 pub struct Catalog {
     pub atlas: String,
     #[serde(rename = "broadworksIdpProxy")]
@@ -321,6 +387,7 @@ pub struct Message {
 
 // Empty reply.
 //-----------------------------------------------------------------------------------------------
+#[allow(dead_code)]
 #[derive(Deserialize, Serialize, Debug)]
 pub(crate) struct EmptyReply {}
 
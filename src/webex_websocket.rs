@@ -1,122 +1,328 @@
-// TODO: Implement websocket communication to webex.
+// std.
+use std::fmt;
+use std::sync::{Arc, Mutex};
 
-// async.
-use async_std::sync::Mutex;
-use async_std::task;
+// serde.
+use serde::Deserialize;
+use serde_json::{json, Value};
 
-// futurse.
-use futures::SinkExt;
+// Tokio.
+use rocket::tokio;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Receiver;
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+use tokio_tungstenite::{connect_async, tungstenite};
 
-// serde.
-use serde_json::json;
+// Future.
+use futures_util::SinkExt;
 
-// std.
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-
-// warp
-use warp::Filter;
-
-// TODO: Direct client access.
-use webex_teams_sdk::WebexApi;
-
-// Websockets.
-use websockets;
-
-#[derive(Debug, Clone)]
-struct WebexWebsocketClient {
-    access_token: String,
-    device_url: String,
-    device_info: Option<serde_json::Value>,
-    on_message: Option<Box<dyn Fn(serde_json::Value, serde_json::Value)>>,
-    on_card_action: Option<Box<dyn Fn(serde_json::Value, serde_json::Value)>>,
-    websocket: Option<websockets::WebSocket>,
+// logging.
+use log::{debug, error, info, warn};
+
+// uuid.
+use uuid::Uuid;
+
+// Own.
+use crate::reconnect::{
+    supervise, Connect, ConnectionState, ReconnectPolicy, StateCallback, WsStream,
+};
+use crate::service;
+use crate::shutdown::Shutdown;
+use crate::types::Device;
+
+// ###################################################################
+// Errors raised while connecting to Mercury.
+// ###################################################################
+
+#[derive(Debug)]
+pub enum WebsocketError {
+    Http(reqwest::Error),          // Device registration failed.
+    WebSocket(tungstenite::Error), // Connection to Mercury failed.
+    Serde(serde_json::Error),      // Unexpected payload.
+}
+
+impl fmt::Display for WebsocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebsocketError::Http(e) => write!(f, "Device registration error: {}", e),
+            WebsocketError::WebSocket(e) => write!(f, "Mercury websocket error: {}", e),
+            WebsocketError::Serde(e) => write!(f, "Mercury payload error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WebsocketError {}
+
+impl From<reqwest::Error> for WebsocketError {
+    fn from(e: reqwest::Error) -> Self {
+        WebsocketError::Http(e)
+    }
+}
+
+impl From<tungstenite::Error> for WebsocketError {
+    fn from(e: tungstenite::Error) -> Self {
+        WebsocketError::WebSocket(e)
+    }
+}
+
+impl From<serde_json::Error> for WebsocketError {
+    fn from(e: serde_json::Error) -> Self {
+        WebsocketError::Serde(e)
+    }
+}
+
+// ###################################################################
+// Frame pushed by Mercury. `data.eventType` tells what happened, e.g.
+// "conversation.activity" for messages and card actions.
+// ###################################################################
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MercuryMessage {
+    pub id: String,          // Frame id, acknowledged back to Mercury.
+    pub data: Value,         // Event contents.
+    pub timestamp: i64,      // When the event was sent (unix milliseconds).
+    pub tracking_id: String, // Tracking id, useful when reporting issues to Webex.
+}
+
+impl MercuryMessage {
+    pub fn event_type(&self) -> Option<&str> {
+        self.data.get("eventType").and_then(Value::as_str)
+    }
+}
+
+// ###################################################################
+// Websocket client receiving the bot events from Webex, no public
+// webhook URL required.
+// ###################################################################
+
+#[derive(Clone)]
+pub struct WebexWebsocketClient {
+    token: String,
+    device_name: String,
+    device: Arc<Mutex<Option<Device>>>, // Shared with the connection task, which may re-register.
+    policy: ReconnectPolicy,
+    on_state: Option<StateCallback>,
+    shutdown: Option<Shutdown>,
 }
 
 impl WebexWebsocketClient {
-    fn new(access_token: &str, device_url: &str) -> Self {
+    const DEVICE_NAME: &'static str = "rusty-webex";
+
+    pub fn new(token: &str) -> Self {
         WebexWebsocketClient {
-            access_token: access_token.to_string(),
-            device_url: device_url.to_string(),
-            device_info: None,
-            on_message: None,
-            on_card_action: None,
-            websocket: None,
+            token: token.to_string(),
+            device_name: Self::DEVICE_NAME.to_string(),
+            device: Arc::new(Mutex::new(None)),
+            policy: ReconnectPolicy::default(),
+            on_state: None,
+            shutdown: None,
         }
     }
 
-    fn process_incoming_websocket_message(&self, msg: &serde_json::Value) {
-        // Implement processing of incoming WebSocket messages
-        // Replace the following code with your message processing logic.
-        if let Some(event_type) = msg.get("data").and_then(|data| data.get("eventType")) {
-            match event_type {
-                serde_json::Value::String(event_type) => {
-                    if event_type == "conversation.activity" {
-                        // Handle conversation activity
-                        // ...
-
-                        if let Some(verb) = msg
-                            .get("data")
-                            .and_then(|data| data.get("activity"))
-                            .and_then(|activity| activity.get("verb"))
-                        {
-                            // Handle verb (e.g., "post" or "cardAction")
-                            if verb == "post" {
-                                // Handle "post" activity
-                                // ...
-                            } else if verb == "cardAction" {
-                                // Handle "cardAction" activity
-                                // ...
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    // Handle other event types
-                }
+    // ------------------------------------------------------------------------------
+    // Name of the registered device, shown in the device list of the bot.
+    // ------------------------------------------------------------------------------
+
+    pub fn device_name(mut self, name: &str) -> Self {
+        self.device_name = name.to_string();
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Backoff and keepalive settings of the connection.
+    // ------------------------------------------------------------------------------
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Close the connection when the bot server shuts down.
+    // ------------------------------------------------------------------------------
+
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Called on every connection state change, e.g. to alert when the bot is offline.
+    // ------------------------------------------------------------------------------
+
+    pub fn on_state_change<F: Fn(&ConnectionState) + Send + Sync + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.on_state = Some(Arc::new(callback));
+        self
+    }
+
+    // ------------------------------------------------------------------------------
+    // Find the device of the bot, only once per client. The WDM service is taken
+    // from the catalog; a device registered under our name is reused and its
+    // duplicates, left behind by previous runs, are deleted.
+    // ------------------------------------------------------------------------------
+
+    pub async fn get_device_info(&self) -> Result<Device, WebsocketError> {
+        if let Some(device) = self.device.lock().unwrap().clone() {
+            return Ok(device);
+        }
+
+        let wdm_url = match service::get_catalog(&self.token).await {
+            Ok(catalog) if !catalog.wdm.is_empty() => catalog.wdm,
+            Ok(_) => service::WDM_URI.to_string(),
+            Err(e) => {
+                warn!(
+                    "Could not fetch the service catalog, using the default WDM: {}",
+                    e
+                );
+                service::WDM_URI.to_string()
+            }
+        };
+
+        let mut devices = service::list_devices(&self.token, &wdm_url)
+            .await?
+            .into_iter()
+            .filter(|device| device.name == self.device_name);
+
+        let device = match devices.next() {
+            Some(device) => {
+                info!("Reusing the device {}", device.url);
+                device
+            }
+            None => self.register(&wdm_url).await?,
+        };
+
+        for stale in devices {
+            match service::delete_device(&self.token, &stale.url).await {
+                Ok(()) => info!("Deleted the stale device {}", stale.url),
+                Err(e) => warn!("Could not delete the stale device {}: {}", stale.url, e),
             }
         }
-    }
 
-    fn run(&self) {
-        // Implement the WebSocket connection logic here
-        // ...
+        *self.device.lock().unwrap() = Some(device.clone());
+        Ok(device)
     }
 
-    fn get_base64_message_id(&self, activity: &serde_json::Value) -> Option<String> {
-        // Implement logic to get the base64 message ID
-        // ...
+    // ------------------------------------------------------------------------------
+    // Delete the device registration, e.g. when the bot is shutting down for good.
+    // ------------------------------------------------------------------------------
 
-        // Return the base64 message ID or None
-        None
+    pub async fn unregister(&self) -> Result<(), WebsocketError> {
+        let device = self.device.lock().unwrap().take();
+        if let Some(device) = device {
+            service::delete_device(&self.token, &device.url).await?;
+            info!("Deleted the device {}", device.url);
+        }
+        Ok(())
     }
 
-    fn ack_message(&self, message_id: &str) {
-        // Implement message acknowledgment
-        // ...
+    async fn register(&self, wdm_url: &str) -> Result<Device, WebsocketError> {
+        let device = service::register_device(
+            &self.token,
+            wdm_url,
+            &Device {
+                device_name: self.device_name.clone(),
+                device_type: "DESKTOP".to_string(),
+                localized_model: "rust".to_string(),
+                model: "rust".to_string(),
+                name: self.device_name.clone(),
+                system_name: std::env::consts::OS.to_string(),
+                system_version: env!("CARGO_PKG_VERSION").to_string(),
+                ..Default::default()
+            },
+        )
+        .await?;
+        info!("Registered the device {}", device.url);
+        Ok(device)
     }
 
-    fn get_device_info(&self, check_existing: bool) -> Option<serde_json::Value> {
-        // Implement logic to get device information
-        // ...
+    // ------------------------------------------------------------------------------
+    // Open the websocket of the device and authorize with the token. After a failed
+    // attempt the device is looked up again, in case it was deleted.
+    // ------------------------------------------------------------------------------
 
-        // Return the device information or None
-        None
+    async fn connect(&self, register: bool) -> Result<WsStream, WebsocketError> {
+        if register {
+            self.device.lock().unwrap().take();
+        }
+        let web_socket_url = self.get_device_info().await?.web_socket_url;
+        let (mut stream, _) = connect_async(web_socket_url.as_str()).await?;
+
+        let authorization = json!({
+            "id": Uuid::new_v4().to_string(),
+            "type": "authorization",
+            "data": { "token": format!("Bearer {}", self.token) },
+        });
+        stream
+            .send(WsMessage::Text(authorization.to_string()))
+            .await?;
+        info!("Connected to Mercury: {}", web_socket_url);
+        Ok(stream)
     }
-}
 
-async fn websocket_recv(client: Arc<Mutex<WebexWebsocketClient>>) {
-    // Implement the WebSocket receive logic here
-    // ...
-}
+    // ------------------------------------------------------------------------------
+    // Register the device and start yielding the events. The connection is kept
+    // alive and re-established with backoff when it drops; the channel only
+    // closes once the client gives up.
+    // ------------------------------------------------------------------------------
+
+    pub async fn run(&self) -> Result<Receiver<MercuryMessage>, WebsocketError> {
+        self.get_device_info().await?;
+
+        let (raw_sender, mut raw_receiver) = mpsc::channel::<WsMessage>(100);
+        let (ack_sender, ack_receiver) = mpsc::channel::<WsMessage>(100);
+        let (sender, receiver) = mpsc::channel(100);
+
+        let client = self.clone();
+        let connect: Connect = Box::new(move |register| {
+            let client = client.clone();
+            Box::pin(async move { client.connect(register).await.map_err(|e| e.to_string()) })
+        });
+        tokio::spawn(supervise(
+            connect,
+            self.policy.clone(),
+            self.on_state.clone(),
+            self.shutdown.clone(),
+            raw_sender,
+            Some(ack_receiver),
+        ));
 
-async fn connect_and_listen(client: Arc<Mutex<WebexWebsocketClient>>) {
-    // Implement the WebSocket connection and listening logic here
-    // ...
+        tokio::spawn(async move {
+            while let Some(frame) = raw_receiver.recv().await {
+                let text = match frame {
+                    WsMessage::Text(text) => text,
+                    WsMessage::Binary(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                    _ => continue,
+                };
+
+                let message = match serde_json::from_str::<MercuryMessage>(&text) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Ignoring an unexpected Mercury frame: {}", e);
+                        continue;
+                    }
+                };
+
+                if !message.id.is_empty()
+                    && ack_sender.send(ack_message(&message.id)).await.is_err()
+                {
+                    error!("Could not acknowledge the Mercury frame {}", message.id);
+                }
+
+                debug!("Mercury event: {:?}", message.event_type());
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
 }
 
-async fn run_listener(client: Arc<Mutex<WebexWebsocketClient>>) {
-    // Implement the listener logic here
-    // ...
+fn ack_message(message_id: &str) -> WsMessage {
+    WsMessage::Text(json!({ "type": "ack", "messageId": message_id }).to_string())
 }